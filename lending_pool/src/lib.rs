@@ -7,24 +7,51 @@ extern crate alloc;
 
 use alloc::vec::Vec;
 
+use alloy_sol_types::{sol, SolCall, SolValue};
 use stylus_sdk::{
     alloy_primitives::{
         U256, Address, 
         U32, U64
-    }, prelude::*
+    }, prelude::*,
+    stylus_core::{calls::context::Call, log},
 };
 
+#[cfg(test)]
+#[path = "../../test_hostio.rs"]
+mod test_hostio;
+
 sol_interface! {
     interface IERC20 {
-        function transferFrom(address from, address to, uint256 tokens) external;
-        function transfer(address to, uint256 tokens) external;
         function balanceOf(address owner) external view returns (uint256);
     }
 }
 
+const BPS_DENOMINATOR: u32 = 10_000;
+const SECONDS_PER_MONTH: u64 = 2_629_746; // average Gregorian month
+const MONTHS_PER_YEAR: u64 = 12;
+// scale of get_share_price
+const SHARE_PRICE_SCALE: u64 = 1_000_000_000;
+// scale of accumulated_interest_per_share
+const ACC_PRECISION: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+
+sol! {
+    // encoded by hand so tokens that return nothing (USDT) still work
+    function transfer(address to, uint256 value) external returns (bool);
+    function transferFrom(address from, address to, uint256 value) external returns (bool);
+
+    event InterestAccrued(uint256 amount, uint256 new_acc_per_share, uint256 total_liquidity);
+    event InterestClaimed(address indexed lender, uint256 amount);
+    event Borrowed(uint256 indexed loan_id, address indexed borrower, uint256 amount);
+    // interest is the lenders' share, reserve the cut taken before it
+    event Repaid(uint256 indexed loan_id, uint256 principal, uint256 interest, uint256 reserve);
+    event BadDebtRecorded(uint256 indexed loan_id, uint256 loss);
+    event TokenMigrated(address indexed old_token, address indexed new_token);
+}
+
 sol_storage! {
     #[entrypoint]
     pub struct LendingPool {
+        address admin;
         address usdc_token;
         address loan_manager;
         uint32 base_interest_rate;
@@ -32,18 +59,46 @@ sol_storage! {
 
         uint256 total_liquidity;
         uint256 total_borrowed;
+        uint256 total_disbursed; // lifetime sum of borrows, never decremented
         uint256 total_interest_earned;
         uint256 accumulated_interest_per_share;
+        uint256 acc_interest_remainder;
+        uint64 last_interest_update; // last borrow, repay or write-off, where accrual projections start
+        uint256 accrued_projection; // gross interest projected up to last_interest_update, not yet repaid
+        uint256 borrowed_rate_weight; // sum of outstanding principal x its loan's rate in bps
+        mapping(uint256 => LoanAccrual) loan_accruals; // each loan's share of the projection
+        uint256 total_shares;
+        uint256 lender_count;
+        uint256 reserve_balance;
+        uint256 total_bad_debt;
+        uint32 reserve_factor_bps;
+        uint256 deposit_cap;
+        uint64 interest_vesting_period;
+        uint64 withdrawal_cooldown;
+
+        bool deposits_paused;
+        bool withdrawals_paused;
+        bool borrows_paused;
+        bool locked;
 
         mapping(address => LenderInfo) lenders;
+        mapping(uint256 => uint256) loan_interest_contributed;
+    }
+
+    pub struct LoanAccrual {
+        uint32 rate_bps; // rate the loan was drawn at
+        uint256 principal; // still lent out
+        uint256 accrued; // projected up to updated_at, less what its repays settled
+        uint64 updated_at;
     }
 
     pub struct LenderInfo {
         uint256 deposit_amount;
         uint64 deposit_timestamp;
-        uint256 earned_interest;
         uint32 share_percentage;
-        uint256 last_acc_interest_per_share;
+        uint256 shares;
+        uint64 last_claim_timestamp;
+        uint256 last_claim_acc_per_share;
     }
 }
 
@@ -55,74 +110,116 @@ impl LendingPool {
         if self.loan_manager.get() != Address::ZERO {
             return Err(b"Already initialized".to_vec());
         }
+        self.admin.set(self.vm().msg_sender());
         self.loan_manager.set(loan_manager);
         self.usdc_token.set(usdc_token);
         self.base_interest_rate.set(U32::from(base_rate));
         self.max_utilization.set(U32::from(9000)); // 90%
+        self.deposit_cap.set(U256::MAX); // uncapped
+        self.interest_vesting_period.set(U64::from(SECONDS_PER_MONTH));
+        Ok(())
+    }
+
+    pub fn set_deposits_paused(&mut self, paused: bool) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self.deposits_paused.set(paused);
+        Ok(())
+    }
+
+    pub fn set_withdrawals_paused(&mut self, paused: bool) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self.withdrawals_paused.set(paused);
+        Ok(())
+    }
+
+    pub fn set_borrows_paused(&mut self, paused: bool) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self.borrows_paused.set(paused);
+        Ok(())
+    }
+
+    pub fn pause_all(&mut self) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self._set_all_paused(true);
+        Ok(())
+    }
+
+    pub fn unpause_all(&mut self) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self._set_all_paused(false);
         Ok(())
     }
 
+    // (deposits, withdrawals, borrows)
+    pub fn get_pause_flags(&self) -> (bool, bool, bool) {
+        (
+            self.deposits_paused.get(),
+            self.withdrawals_paused.get(),
+            self.borrows_paused.get()
+        )
+    }
+
     pub fn deposit(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        self._lock()?;
         let sender: Address = self.vm().msg_sender();
+        if self.deposits_paused.get() {
+            return Err(b"Deposits paused".to_vec());
+        }
         if amount == U256::ZERO {
             return Err(b"Invalid amount".to_vec());
         }
+        self._check_deposit_cap(amount)?;
 
         // toks from lender
-        {
-            let token = self.usdc_token.get();
-            let contract = self.vm().contract_address();
-            let erc20 = IERC20::new(token);
-            let _ = erc20.transfer_from(&mut *self, sender, contract, amount);
-        }
+        let contract = self.vm().contract_address();
+        self._safe_transfer_from(sender, contract, amount)?;
 
-        let pending;
-        {
-            pending = self.update_interest(sender);
-        }
+        self._credit_deposit(sender, amount);
 
-        // lender info
-        let lender = self.lenders.get(sender);
-        let new_deposit = lender.deposit_amount.get().saturating_add(amount);
-        let current_time =  self.vm().block_timestamp();
-        
+        self._unlock();
+        Ok(())
+    }
 
-        // pending interest
-        let mut accrued_interest = U256::ZERO;
-        {
-            if lender.deposit_amount.get() == U256::ZERO {
-            
-                if pending > U256::ZERO {
-                    accrued_interest = pending;
-                }
-            }
+    // Bootstrap the pool with known lenders, funded in one pull from the admin.
+    pub fn seed_deposits(&mut self, lenders: Vec<Address>, amounts: Vec<U256>) -> Result<(), Vec<u8>> {
+        self._lock()?;
+        self._only_admin()?;
+        if self.deposits_paused.get() {
+            return Err(b"Deposits paused".to_vec());
+        }
+        if lenders.len() != amounts.len() {
+            return Err(b"Length mismatch".to_vec());
         }
 
-        // set total liquidity
-        let new_total_liq = self.total_liquidity.get().saturating_add(amount);
-        self.total_liquidity.set(new_total_liq);
-
-        // set share percentage
-        let new_share = if new_total_liq > U256::ZERO {
-            (new_deposit * U256::from(10000)) / new_total_liq
-        } else {
-            U256::from(10000)
-        };
+        let mut total = U256::ZERO;
+        for amount in amounts.iter() {
+            if *amount == U256::ZERO {
+                return Err(b"Invalid amount".to_vec());
+            }
+            total = total.saturating_add(*amount);
+        }
+        self._check_deposit_cap(total)?;
 
         {
-            // set deposit values
-            let mut lender = self.lenders.setter(sender);
-            lender.earned_interest.set(accrued_interest);
-            lender.share_percentage.set(U32::from(new_share));
-            lender.deposit_amount.set(new_deposit);
-            lender.deposit_timestamp.set(U64::from(current_time));
+            let sender = self.vm().msg_sender();
+            let contract = self.vm().contract_address();
+            self._safe_transfer_from(sender, contract, total)?;
+        }
+
+        for (lender, amount) in lenders.into_iter().zip(amounts) {
+            self._credit_deposit(lender, amount);
         }
 
+        self._unlock();
         Ok(())
     }
 
     pub fn withdraw(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        self._lock()?;
         let sender = self.vm().msg_sender();
+        if self.withdrawals_paused.get() {
+            return Err(b"Withdrawals paused".to_vec());
+        }
         
         // Validate amount
         if amount == U256::ZERO {
@@ -132,73 +229,236 @@ impl LendingPool {
         // Get lender info
         let lender = self.lenders.getter(sender);
         let deposit_amount = lender.deposit_amount.get();
+        let lender_shares = lender.shares.get();
         
         // Check sufficient balance
         if deposit_amount < amount {
             return Err(b"Insufficient balance".to_vec());
         }
+        if U64::from(self.vm().block_timestamp()) < self.get_unlock_time(sender) {
+            return Err(b"Withdrawal cooldown active".to_vec());
+        }
 
-        // Check pool liquidity
+        // Burn the slice of shares backing this part of the deposit and pay
+        // what those shares are worth. After a write-off that is less than
+        // the deposit, so every lender carries the loss pro rata.
+        let burned = if amount == deposit_amount {
+            lender_shares
+        } else {
+            (lender_shares * amount) / deposit_amount
+        };
+        let payout = self._amount_for_shares(burned);
         let total_liq = self.total_liquidity.get();
-        let available = total_liq.saturating_sub(self.total_borrowed.get());
-        
-        if amount > available {
+        let principal = payout.min(amount).min(total_liq);
+        let mut interest = self._vested_interest(sender, payout - principal);
+
+        // the last lender out takes whatever tracked interest is left, so no
+        // rounding dust or forfeited interest is stranded in an empty pool
+        let last_out = amount == deposit_amount && self.lender_count.get() == U256::from(1);
+        if last_out {
+            interest = self.total_interest_earned.get();
+        }
+        let interest = interest.min(self.total_interest_earned.get());
+        let total_withdraw = principal + interest;
+
+        // Check pool liquidity
+        if principal > total_liq - self.total_borrowed.get() {
             return Err(b"Insufficient pool liquidity".to_vec());
         }
 
-        // Claim pending interest
-        let pending = self.update_interest(sender);
+        // catch accounting drift before the transfer fails on its own
+        {
+            let contract = self.vm().contract_address();
+            let token = IERC20::new(self.usdc_token.get());
+            let balance = token.balance_of(&*self, contract)?;
+            if total_withdraw > balance {
+                return Err(b"Pool balance insufficient for payout".to_vec());
+            }
+        }
         
         // Calculate new deposit amount
-        let new_deposit = deposit_amount.saturating_sub(amount);
+        let new_deposit = deposit_amount - amount;
         
-        // Update total liquidity
-        let new_total_liq = total_liq.saturating_sub(amount);
-        self.total_liquidity.set(U256::from(new_total_liq));
+        // Update pool totals
+        let new_total_liq = total_liq - principal;
+        self.total_liquidity.set(new_total_liq);
+        self.total_shares.set(self.total_shares.get() - burned);
+        self.total_interest_earned
+            .set(self.total_interest_earned.get() - interest);
+
+        if new_deposit == U256::ZERO {
+            self.lender_count.set(self.lender_count.get().saturating_sub(U256::from(1)));
+        }
+        if last_out {
+            self.total_interest_earned.set(U256::ZERO);
+            self.accumulated_interest_per_share.set(U256::ZERO);
+            self.acc_interest_remainder.set(U256::ZERO);
+        }
 
         // Update lender's state
         {
             let mut lender = self.lenders.setter(sender);
             lender.deposit_amount.set(new_deposit);
+            lender.shares.set(lender_shares - burned);
             
             // Update share percentage
             let new_share = if new_total_liq > U256::ZERO {
-                (new_deposit * U256::from(10000)) / new_total_liq
+                new_deposit.saturating_mul(U256::from(BPS_DENOMINATOR)) / new_total_liq
             } else {
                 U256::ZERO
             };
-            lender.share_percentage.set(U32::from(new_share));
+            lender.share_percentage.set(Self::_share_bps(new_share));
         }
 
         // Transfer tokens to sender
-        let total_withdraw = amount.saturating_add(pending);
-        let token = IERC20::new(self.usdc_token.get());
-        
-        let _ = token.transfer(&mut *self, sender, total_withdraw);
+        self._safe_transfer(sender, total_withdraw)?;
 
+        self._unlock();
         Ok(())
     }
 
-    pub fn borrow(&mut self, amount: U256, borrower: Address) {
+    // Pays out the lender's vested interest by burning the shares it's worth.
+    // Unlike withdraw, the principal is untouched: deposit_amount and
+    // deposit_timestamp stay as they are and the remaining shares keep
+    // earning on the full deposit.
+    pub fn claim_interest(&mut self) -> Result<U256, Vec<u8>> {
+        self._lock()?;
+        let sender = self.vm().msg_sender();
+        if self.withdrawals_paused.get() {
+            return Err(b"Withdrawals paused".to_vec());
+        }
+
+        let lender_shares = self.lenders.getter(sender).shares.get();
+        let interest = self.get_claimable_interest(sender);
+        if interest == U256::ZERO {
+            return Err(b"No interest to claim".to_vec());
+        }
+        let burned = self._shares_for_amount(interest).min(lender_shares);
+
+        self.total_shares.set(self.total_shares.get().saturating_sub(burned));
+        self.total_interest_earned
+            .set(self.total_interest_earned.get().saturating_sub(interest));
+
+        {
+            let now = U64::from(self.vm().block_timestamp());
+            let acc = self.accumulated_interest_per_share.get();
+            let mut lender = self.lenders.setter(sender);
+            lender.shares.set(lender_shares - burned);
+            lender.last_claim_timestamp.set(now);
+            lender.last_claim_acc_per_share.set(acc);
+        }
+
+        self._safe_transfer(sender, interest)?;
+
+        let event = InterestClaimed { lender: sender, amount: interest };
+        log(self.vm(), event);
+        self._unlock();
+        Ok(interest)
+    }
+
+    // what claim_interest would pay out right now
+    pub fn get_claimable_interest(&self, lender: Address) -> U256 {
+        let info = self.lenders.getter(lender);
+        let gain = self
+            ._amount_for_shares(info.shares.get())
+            .saturating_sub(info.deposit_amount.get());
+        self._vested_interest(lender, gain)
+    }
+
+    // interest credited to the lender's deposit since the last checkpoint
+    // (their last claim, or first deposit)
+    pub fn get_interest_since_last_claim(&self, lender: Address) -> U256 {
+        let lender = self.lenders.getter(lender);
+        let acc = self.accumulated_interest_per_share.get();
+        let delta = acc.saturating_sub(lender.last_claim_acc_per_share.get());
+        (lender.deposit_amount.get() * delta) / ACC_PRECISION
+    }
+
+    // Interest since the last claim plus the lender's share of what the
+    // outstanding loans have accrued and not yet repaid, estimated at the
+    // rates they were drawn at. Only the first part is actually in the pool yet.
+    pub fn get_pending_interest(&self, lender: Address) -> U256 {
+        let realized = self.get_interest_since_last_claim(lender);
+        let total_liq = self.total_liquidity.get();
+        if total_liq == U256::ZERO {
+            return realized;
+        }
+        let deposit = self.lenders.getter(lender).deposit_amount.get();
+        realized + deposit * self._projected_accrual() / total_liq
+    }
+
+    // (timestamp, accumulated_interest_per_share) at the last checkpoint
+    pub fn get_last_claim(&self, lender: Address) -> (U64, U256) {
+        let lender = self.lenders.getter(lender);
+        (lender.last_claim_timestamp.get(), lender.last_claim_acc_per_share.get())
+    }
+
+    // loan_id is the loan manager's id for the loan being funded, logged so
+    // pool flows can be matched to loans; rate_bps is the loan's rate, which
+    // weights the pool's accrual projection
+    pub fn borrow(
+        &mut self,
+        amount: U256,
+        borrower: Address,
+        loan_id: U256,
+        rate_bps: u32,
+    ) -> Result<(), Vec<u8>> {
+        self._lock()?;
         let caller = self.vm().msg_sender();
-        assert!(caller == self.loan_manager.get(), "Not LoanManager");
-        assert!(amount > U256::ZERO, "Invalid amount");
+        if caller != self.loan_manager.get() {
+            return Err(b"Not LoanManager".to_vec());
+        }
+        if self.borrows_paused.get() {
+            return Err(b"Borrows paused".to_vec());
+        }
+        if amount == U256::ZERO {
+            return Err(b"Invalid amount".to_vec());
+        }
 
+        // reverts with these exact bytes so the loan manager can tell a
+        // liquidity shortfall apart from other failures
         let total_liq = self.total_liquidity.get();
         let total_borrowed = self.total_borrowed.get();
-        assert!(total_liq >= total_borrowed + amount, "Insufficient liquidity");
+        if total_liq < total_borrowed + amount {
+            return Err(b"Insufficient liquidity".to_vec());
+        }
 
+        self._checkpoint_accrual();
+        self._checkpoint_loan_accrual(loan_id);
         self.total_borrowed.set(total_borrowed + amount);
+        self.total_disbursed.set(self.total_disbursed.get() + amount);
+        {
+            let mut accrual = self.loan_accruals.setter(loan_id);
+            accrual.rate_bps.set(U32::from(rate_bps));
+            let principal = accrual.principal.get();
+            accrual.principal.set(principal + amount);
+        }
+        let weight = self.borrowed_rate_weight.get();
+        self.borrowed_rate_weight.set(weight + amount * U256::from(rate_bps));
 
-        let token = IERC20::new(self.usdc_token.get());
-        let _ = token.transfer(&mut *self, borrower, amount);
-        // assert!(success, "Borrow transfer failed");
+        self._safe_transfer(borrower, amount)?;
+        log(self.vm(), Borrowed { loan_id, borrower, amount });
 
+        self._unlock();
+        Ok(())
     }
 
-    pub fn repay(&mut self, principal: U256, interest: U256) {
+    pub fn repay(&mut self, principal: U256, interest: U256, loan_id: U256) -> Result<(), Vec<u8>> {
+        self._lock()?;
         let caller = self.vm().msg_sender();
-        assert!(caller == self.loan_manager.get(), "Not LoanManager");
+        if caller != self.loan_manager.get() {
+            return Err(b"Not LoanManager".to_vec());
+        }
+
+        // divert part of the interest into the reserve
+        let to_reserve =
+            (interest * U256::from(self.reserve_factor_bps.get())) / U256::from(BPS_DENOMINATOR);
+        let interest = interest - to_reserve;
+        self.reserve_balance.set(self.reserve_balance.get() + to_reserve);
+
+        // lenders' share of the interest, per loan
+        let contributed = self.loan_interest_contributed.get(loan_id);
+        self.loan_interest_contributed.insert(loan_id, contributed + interest);
 
         let mut total_borrowed = self.total_borrowed.get();
         let mut total_interest_earned = self.total_interest_earned.get();
@@ -206,60 +466,838 @@ impl LendingPool {
         total_borrowed -= principal;
         total_interest_earned += interest;
 
+        // what the loan paid stops counting as projected, as does the
+        // principal it no longer owes. Only interest the loan had accrued
+        // comes off, so a fee paid along with the draw leaves it alone.
+        self._checkpoint_accrual();
+        let accrued = self._checkpoint_loan_accrual(loan_id);
+        let settled = (interest + to_reserve).min(accrued);
+        self.loan_accruals.setter(loan_id).accrued.set(accrued - settled);
+        let projected = self.accrued_projection.get();
+        self.accrued_projection.set(projected.saturating_sub(settled));
+        self._reduce_rate_weight(loan_id, principal);
+
         self.total_borrowed.set(total_borrowed);
         self.total_interest_earned.set(total_interest_earned);
 
-        // Update accumulated interest per share
+        // Update accumulated interest per share (index only, payouts come from share price).
+        // The division's remainder carries into the next repay so small
+        // amounts against a large pool aren't truncated away.
         let total_liq = self.total_liquidity.get();
         if interest > U256::ZERO && total_liq > U256::ZERO {
-            let interest_per_share = (interest * U256::from(1_000_000_000u64)) / total_liq;
-            let mut acc = self.accumulated_interest_per_share.get();
-            acc += interest_per_share;
+            let acc = self.accumulated_interest_per_share.get();
+            let numerator = interest
+                .checked_mul(ACC_PRECISION)
+                .and_then(|n| n.checked_add(self.acc_interest_remainder.get()));
+            let acc = match numerator {
+                Some(n) => {
+                    self.acc_interest_remainder.set(n % total_liq);
+                    acc.saturating_add(n / total_liq)
+                }
+                // only reachable with amounts far beyond any token supply
+                None => acc.saturating_add((interest / total_liq).saturating_mul(ACC_PRECISION)),
+            };
             self.accumulated_interest_per_share.set(acc);
         }
+
+        if interest > U256::ZERO {
+            let event = InterestAccrued {
+                amount: interest,
+                new_acc_per_share: self.accumulated_interest_per_share.get(),
+                total_liquidity: total_liq,
+            };
+            log(self.vm(), event);
+        }
+
+        let event = Repaid { loan_id, principal, interest, reserve: to_reserve };
+        log(self.vm(), event);
+
+        self._unlock();
+        Ok(())
+    }
+
+    // Books a written-off loan's principal as a loss. It comes out of the
+    // lenders' liquidity, so the share price drops with it.
+    pub fn write_off(&mut self, loan_id: U256, loss: U256) -> Result<(), Vec<u8>> {
+        if self.vm().msg_sender() != self.loan_manager.get() {
+            return Err(b"Not LoanManager".to_vec());
+        }
+        let loss = loss.min(self.total_borrowed.get());
+        // the loan stops accruing, and what it had accrued is never coming
+        self._checkpoint_accrual();
+        self._checkpoint_loan_accrual(loan_id);
+        let principal = self.loan_accruals.getter(loan_id).principal.get();
+        self._reduce_rate_weight(loan_id, principal);
+        self.total_borrowed.set(self.total_borrowed.get() - loss);
+        self.total_liquidity
+            .set(self.total_liquidity.get().saturating_sub(loss));
+        self.total_bad_debt.set(self.total_bad_debt.get() + loss);
+
+        let event = BadDebtRecorded { loan_id, loss };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    pub fn get_total_bad_debt(&self) -> U256 {
+        self.total_bad_debt.get()
+    }
+
+    // Switches the pool to a replacement token (e.g. USDC.e to native USDC).
+    // Everything must be paused with nothing lent out, and the new token must
+    // already be here to cover all the pool owes: deposits, unpaid interest
+    // and the reserve.
+    pub fn migrate_token(&mut self, new_token: Address) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        if self.get_pause_flags() != (true, true, true) {
+            return Err(b"Pool not paused".to_vec());
+        }
+        if self.total_borrowed.get() > U256::ZERO {
+            return Err(b"Loans outstanding".to_vec());
+        }
+        let old_token = self.usdc_token.get();
+        if new_token == Address::ZERO || new_token == old_token {
+            return Err(b"Invalid token".to_vec());
+        }
+
+        let owed = self._total_assets().saturating_add(self.reserve_balance.get());
+        let contract = self.vm().contract_address();
+        let balance = IERC20::new(new_token).balance_of(&*self, contract)?;
+        if balance < owed {
+            return Err(b"New token balance too low".to_vec());
+        }
+
+        self.usdc_token.set(new_token);
+        log(self.vm(), TokenMigrated { old_token, new_token });
+        Ok(())
+    }
+
+    // interest a loan has paid into the pool, net of the reserve cut
+    pub fn get_loan_interest_contributed(&self, loan_id: U256) -> U256 {
+        self.loan_interest_contributed.get(loan_id)
+    }
+
+    pub fn set_reserve_factor_bps(&mut self, factor_bps: u32) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        if factor_bps > BPS_DENOMINATOR {
+            return Err(b"Invalid reserve factor".to_vec());
+        }
+        self.reserve_factor_bps.set(U32::from(factor_bps));
+        Ok(())
+    }
+
+    // pays reserve out to lenders, e.g. to cover interest lost on a default
+    pub fn release_reserve(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        let reserve = self.reserve_balance.get();
+        if amount > reserve {
+            return Err(b"Insufficient reserve".to_vec());
+        }
+        self.reserve_balance.set(reserve - amount);
+        self.total_interest_earned
+            .set(self.total_interest_earned.get() + amount);
+        Ok(())
+    }
+
+    pub fn get_reserve_balance(&self) -> U256 {
+        self.reserve_balance.get()
+    }
+
+    pub fn get_reserve_factor_bps(&self) -> U32 {
+        self.reserve_factor_bps.get()
+    }
+
+    // U256::MAX means no cap
+    pub fn set_deposit_cap(&mut self, cap: U256) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self.deposit_cap.set(cap);
+        Ok(())
+    }
+
+    pub fn get_deposit_cap(&self) -> U256 {
+        self.deposit_cap.get()
+    }
+
+    pub fn get_remaining_capacity(&self) -> U256 {
+        self.deposit_cap.get().saturating_sub(self.total_liquidity.get())
+    }
+
+    // 0 turns vesting off, so interest counts in full straight away
+    pub fn set_interest_vesting_period(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self.interest_vesting_period.set(U64::from(seconds));
+        Ok(())
+    }
+
+    pub fn get_interest_vesting_period(&self) -> U64 {
+        self.interest_vesting_period.get()
+    }
+
+    // minimum time between a deposit and withdrawing it, 0 turns it off
+    pub fn set_withdrawal_cooldown(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        self._only_admin()?;
+        self.withdrawal_cooldown.set(U64::from(seconds));
+        Ok(())
+    }
+
+    pub fn get_withdrawal_cooldown(&self) -> U64 {
+        self.withdrawal_cooldown.get()
+    }
+
+    // when the lender can next withdraw; deposits pull deposit_timestamp forward
+    pub fn get_unlock_time(&self, lender: Address) -> U64 {
+        self.lenders
+            .getter(lender)
+            .deposit_timestamp
+            .get()
+            .saturating_add(self.withdrawal_cooldown.get())
     }
 
     pub fn get_available_liquidity(&self) -> U256 {
         self.total_liquidity.get() - self.total_borrowed.get()
     }
 
+    pub fn get_total_liquidity(&self) -> U256 {
+        self.total_liquidity.get()
+    }
+
+    pub fn get_total_disbursed(&self) -> U256 {
+        self.total_disbursed.get()
+    }
+
     pub fn get_utilization_rate(&self) -> U256 {
         let total_liq = self.total_liquidity.get();
         if total_liq == U256::ZERO {
             return U256::ZERO;
         }
         let total_borrowed = self.total_borrowed.get();
-        (total_borrowed * U256::from(10000)) / total_liq
+        (total_borrowed * U256::from(BPS_DENOMINATOR)) / total_liq
     }
 
-    pub fn get_lender_info(&self, lender: Address) -> (U256, U256, U32, U256) {
-        let lender = self.lenders.getter(lender);
+    pub fn get_lender_info(&self, lender_addr: Address) -> (U256, U256, U32, U256) {
+        let lender = self.lenders.getter(lender_addr);
+        let deposit = lender.deposit_amount.get();
+        let shares = lender.shares.get();
+        let gain = self._amount_for_shares(shares).saturating_sub(deposit);
         (
-            lender.deposit_amount.get(), 
-            lender.earned_interest.get(), 
+            deposit, 
+            self._vested_interest(lender_addr, gain), 
             lender.share_percentage.get(), 
-            lender.last_acc_interest_per_share.get()
+            shares
         )
     }
 
-    fn update_interest(&mut self, lender_addr: Address) -> U256 {
-        let lender = self.lenders.getter(lender_addr);
+    pub fn get_lender_count(&self) -> U256 {
+        self.lender_count.get()
+    }
+
+    pub fn get_shares(&self, lender: Address) -> U256 {
+        self.lenders.getter(lender).shares.get()
+    }
+
+    // value of one share, scaled by 1e9
+    pub fn get_share_price(&self) -> U256 {
+        let total_shares = self.total_shares.get();
+        if total_shares == U256::ZERO {
+            return U256::from(SHARE_PRICE_SCALE);
+        }
+        (self._total_assets() * U256::from(SHARE_PRICE_SCALE)) / total_shares
+    }
+
+    // (base_interest_rate, max_utilization, reserve_factor_bps, deposit_cap,
+    //  interest_vesting_period, deposits_paused, withdrawals_paused, borrows_paused,
+    //  withdrawal_cooldown)
+    pub fn get_parameters(&self) -> (U32, U32, U32, U256, U64, bool, bool, bool, U64) {
+        (
+            self.base_interest_rate.get(),
+            self.max_utilization.get(),
+            self.reserve_factor_bps.get(),
+            self.deposit_cap.get(),
+            self.interest_vesting_period.get(),
+            self.deposits_paused.get(),
+            self.withdrawals_paused.get(),
+            self.borrows_paused.get(),
+            self.withdrawal_cooldown.get(),
+        )
+    }
+}
+
+impl LendingPool {
+    fn _only_admin(&self) -> Result<(), Vec<u8>> {
+        if self.vm().msg_sender() != self.admin.get() {
+            return Err(b"Only admin".to_vec());
+        }
+        Ok(())
+    }
+
+    // Held for the duration of any call that moves tokens. A failed call
+    // reverts the flag along with everything else, so only the success
+    // paths need to release it.
+    fn _lock(&mut self) -> Result<(), Vec<u8>> {
+        if self.locked.get() {
+            return Err(b"Reentrant call".to_vec());
+        }
+        self.locked.set(true);
+        Ok(())
+    }
+
+    fn _unlock(&mut self) {
+        self.locked.set(false);
+    }
+
+    fn _set_all_paused(&mut self, paused: bool) {
+        self.deposits_paused.set(paused);
+        self.withdrawals_paused.set(paused);
+        self.borrows_paused.set(paused);
+    }
+
+    fn _safe_transfer(&mut self, to: Address, value: U256) -> Result<(), Vec<u8>> {
+        self._call_token(&transferCall { to, value }.abi_encode())
+    }
+
+    fn _safe_transfer_from(
+        &mut self,
+        from: Address,
+        to: Address,
+        value: U256,
+    ) -> Result<(), Vec<u8>> {
+        self._call_token(&transferFromCall { from, to, value }.abi_encode())
+    }
+
+    // tokens that return nothing are taken at their word, the rest must return true
+    fn _call_token(&mut self, calldata: &[u8]) -> Result<(), Vec<u8>> {
+        let token = self.usdc_token.get();
+        let returned = self.vm().call(&Call::new(), token, calldata)?;
+        if !returned.is_empty() && !bool::abi_decode(&returned, false).unwrap_or(false) {
+            return Err(b"Token transfer failed".to_vec());
+        }
+        Ok(())
+    }
+
+    fn _check_deposit_cap(&self, amount: U256) -> Result<(), Vec<u8>> {
+        if amount > self.get_remaining_capacity() {
+            return Err(b"Pool deposit cap reached".to_vec());
+        }
+        Ok(())
+    }
+
+    fn _credit_deposit(&mut self, lender_addr: Address, amount: U256) {
+        // mint shares at the current price, before liquidity changes
+        let minted = self._shares_for_amount(amount);
+
+        // lender info
+        let lender = self.lenders.get(lender_addr);
+        let is_new = lender.deposit_amount.get() == U256::ZERO;
+        let new_deposit = lender.deposit_amount.get().saturating_add(amount);
+        let new_shares = lender.shares.get().saturating_add(minted);
+        let current_time = U64::from(self.vm().block_timestamp());
+        // top-ups move the deposit time forward in proportion to their size
+        let deposit_time = if is_new {
+            current_time
+        } else {
+            let old_time = U256::from(lender.deposit_timestamp.get());
+            let weighted = old_time * lender.deposit_amount.get()
+                + U256::from(current_time) * amount;
+            U64::from(weighted / new_deposit)
+        };
+
+        if is_new {
+            self.lender_count.set(self.lender_count.get() + U256::from(1));
+        }
+
+        // set total liquidity
+        let new_total_liq = self.total_liquidity.get().saturating_add(amount);
+        self.total_liquidity.set(new_total_liq);
+        self.total_shares.set(self.total_shares.get().saturating_add(minted));
 
         let acc = self.accumulated_interest_per_share.get();
-        let last_acc = lender.last_acc_interest_per_share.get();
 
-        let mut pending = U256::ZERO;
+        // set share percentage
+        let new_share = if new_total_liq > U256::ZERO {
+            new_deposit.saturating_mul(U256::from(BPS_DENOMINATOR)) / new_total_liq
+        } else {
+            U256::from(BPS_DENOMINATOR)
+        };
+
+        {
+            // set deposit values
+            let mut lender = self.lenders.setter(lender_addr);
+            lender.shares.set(new_shares);
+            lender.share_percentage.set(Self::_share_bps(new_share));
+            lender.deposit_amount.set(new_deposit);
+            lender.deposit_timestamp.set(deposit_time);
+            if is_new {
+                lender.last_claim_timestamp.set(current_time);
+                lender.last_claim_acc_per_share.set(acc);
+            }
+        }
+    }
 
-        if lender.deposit_amount.get() > U256::ZERO {
-            pending = (lender.deposit_amount.get() * (acc.clone() - last_acc)) / U256::from(1_000_000_000u64);
-            pending = lender.earned_interest.get() + pending;
+    // A lender's share of interest vests linearly over the vesting period from
+    // their deposit time, so money parked just before a large repay can't take
+    // a full cut of it. The unvested part stays in the pool for everyone else.
+    fn _vested_interest(&self, lender_addr: Address, interest: U256) -> U256 {
+        let period = self.interest_vesting_period.get();
+        let deposited_at = self.lenders.getter(lender_addr).deposit_timestamp.get();
+        let elapsed = U64::from(self.vm().block_timestamp()).saturating_sub(deposited_at);
+        if period == U64::ZERO || elapsed >= period {
+            return interest;
         }
+        interest * U256::from(elapsed) / U256::from(period)
+    }
+
+    // A lender can't own more than the whole pool, but deposits can outgrow
+    // total liquidity once a loss is written off, so cap it rather than let
+    // the conversion panic.
+    fn _share_bps(share: U256) -> U32 {
+        let capped = share.min(U256::from(BPS_DENOMINATOR));
+        U32::from(capped.to::<u32>())
+    }
+
+    // Lenders' cut of the interest outstanding loans have accrued at their
+    // own rates and not yet repaid, i.e. what the next repays should bring.
+    fn _projected_accrual(&self) -> U256 {
+        let gross = self.accrued_projection.get() + self._accrual_since_update();
+        let to_reserve =
+            gross * U256::from(self.reserve_factor_bps.get()) / U256::from(BPS_DENOMINATOR);
+        gross - to_reserve
+    }
+
+    // gross interest since last_interest_update at the borrow-weighted rate
+    fn _accrual_since_update(&self) -> U256 {
+        let elapsed = U64::from(self.vm().block_timestamp())
+            .saturating_sub(self.last_interest_update.get());
+        Self::_interest_over(self.borrowed_rate_weight.get(), elapsed)
+    }
+
+    // interest on principal x rate (in bps) over `elapsed` seconds
+    fn _interest_over(weight: U256, elapsed: U64) -> U256 {
+        let year = U256::from(SECONDS_PER_MONTH * MONTHS_PER_YEAR);
+        weight * U256::from(elapsed) / (year * U256::from(BPS_DENOMINATOR))
+    }
+
+    // Brings the loan's own projected accrual up to now and returns it. The
+    // loans' accruals add up to accrued_projection, so this is the most a
+    // repay or write-off of that loan can take off it.
+    fn _checkpoint_loan_accrual(&mut self, loan_id: U256) -> U256 {
+        let now = U64::from(self.vm().block_timestamp());
+        let mut accrual = self.loan_accruals.setter(loan_id);
+        let weight = accrual.principal.get() * U256::from(accrual.rate_bps.get());
+        let elapsed = now.saturating_sub(accrual.updated_at.get());
+        let accrued = accrual.accrued.get() + Self::_interest_over(weight, elapsed);
+        accrual.accrued.set(accrued);
+        accrual.updated_at.set(now);
+        accrued
+    }
+
+    // Folds the accrual so far into accrued_projection before the borrowed
+    // amounts change, so moving last_interest_update loses none of it.
+    fn _checkpoint_accrual(&mut self) {
+        let projected = self.accrued_projection.get() + self._accrual_since_update();
+        self.accrued_projection.set(projected);
+        self.last_interest_update.set(U64::from(self.vm().block_timestamp()));
+    }
+
+    // Takes principal off the loan's accrual. A loan with nothing left lent
+    // out brings nothing more, so its leftover projection goes too, and once
+    // nothing is accruing at all nothing more is coming either.
+    fn _reduce_rate_weight(&mut self, loan_id: U256, principal: U256) {
+        let (rate, reduced, remaining, leftover) = {
+            let mut accrual = self.loan_accruals.setter(loan_id);
+            let reduced = principal.min(accrual.principal.get());
+            let remaining = accrual.principal.get() - reduced;
+            accrual.principal.set(remaining);
+            let rate = U256::from(accrual.rate_bps.get());
+            (rate, reduced, remaining, accrual.accrued.get())
+        };
+        let weight = self.borrowed_rate_weight.get().saturating_sub(reduced * rate);
+        self.borrowed_rate_weight.set(weight);
+        if remaining == U256::ZERO {
+            self.loan_accruals.setter(loan_id).accrued.set(U256::ZERO);
+            let projected = self.accrued_projection.get();
+            self.accrued_projection.set(projected.saturating_sub(leftover));
+        }
+        if weight == U256::ZERO {
+            self.accrued_projection.set(U256::ZERO);
+        }
+    }
+
+    // principal plus interest that hasn't been paid out yet
+    fn _total_assets(&self) -> U256 {
+        self.total_liquidity.get().saturating_add(self.total_interest_earned.get())
+    }
+
+    fn _shares_for_amount(&self, amount: U256) -> U256 {
+        let total_shares = self.total_shares.get();
+        let total_assets = self._total_assets();
+        if total_shares == U256::ZERO || total_assets == U256::ZERO {
+            amount
+        } else {
+            (amount * total_shares) / total_assets
+        }
+    }
+
+    fn _amount_for_shares(&self, shares: U256) -> U256 {
+        let total_shares = self.total_shares.get();
+        if total_shares == U256::ZERO {
+            return U256::ZERO;
+        }
+        (shares * self._total_assets()) / total_shares
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_hostio::*;
+    use stylus_sdk::testing::*;
+
+    mod token_abi {
+        alloy_sol_types::sol! {
+            function balanceOf(address owner) external view returns (uint256);
+        }
+    }
+
+    // the pool as the loan manager's ILendingPool declares it
+    mod loan_manager_abi {
+        alloy_sol_types::sol! {
+            function borrow(uint256 amount, address borrower, uint256 loan_id, uint32 rate_bps) external;
+            function repay(uint256 principal, uint256 interest, uint256 loan_id) external;
+        }
+    }
+
+    // dispatches calldata the way the deployed entrypoint would
+    fn route(pool: &mut LendingPool, calldata: &[u8]) -> Option<stylus_sdk::ArbResult> {
+        let selector = u32::from_be_bytes(calldata[..4].try_into().unwrap());
+        <LendingPool as stylus_sdk::abi::Router<LendingPool>>::route(pool, selector, &calldata[4..])
+    }
+
+    const ADMIN: Address = Address::repeat_byte(0xad);
+    const LOAN_MANAGER: Address = Address::repeat_byte(0x1a);
+    const USDC: Address = Address::repeat_byte(0x0c);
+    const LENDER_A: Address = Address::repeat_byte(0xa1);
+    const LENDER_B: Address = Address::repeat_byte(0xb1);
+
+    fn setup() -> (TestVM, LendingPool) {
+        let vm = TestVM::default();
+        vm.set_sender(ADMIN);
+        let mut pool = LendingPool::from(&vm);
+        pool.initialize(LOAN_MANAGER, USDC, 1000).unwrap();
+        pool.set_interest_vesting_period(0).unwrap();
+        // the token holds whatever the pool asks to pay out
+        let balance = token_abi::balanceOfCall { owner: vm.contract_address() }.abi_encode();
+        mock_call(USDC, balance, Ok(U256::MAX.abi_encode()));
+        (vm, pool)
+    }
+
+    fn deposit(vm: &TestVM, pool: &mut LendingPool, lender: Address, amount: U256) {
+        vm.set_sender(lender);
+        pool.deposit(amount).unwrap();
+    }
+
+    #[test]
+    fn withdrawals_share_a_write_off_pro_rata() {
+        let (vm, mut pool) = setup();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+        deposit(&vm, &mut pool, LENDER_B, amount);
+
+        vm.set_sender(LOAN_MANAGER);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(1), 1_000).unwrap();
+        pool.write_off(U256::from(1), amount).unwrap();
+
+        // half the pool is gone, so each lender gets half their deposit back
+        vm.set_sender(LENDER_A);
+        pool.withdraw(amount).unwrap();
+        assert_eq!(pool.get_total_liquidity(), amount / U256::from(2));
+
+        vm.set_sender(LENDER_B);
+        pool.withdraw(amount).unwrap();
+        assert_eq!(pool.get_total_liquidity(), U256::ZERO);
+        assert_eq!(pool.get_lender_count(), U256::ZERO);
+    }
+
+    #[test]
+    fn borrow_answers_the_loan_managers_selector() {
+        let (vm, mut pool) = setup();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+
+        vm.set_sender(LOAN_MANAGER);
+        let call = loan_manager_abi::borrowCall {
+            amount,
+            borrower: LOAN_MANAGER,
+            loan_id: U256::from(7),
+            rate_bps: 1_000,
+        };
+        let routed = route(&mut pool, &call.abi_encode()).expect("unknown selector");
+        assert_eq!(routed, Ok(Vec::new()));
+        assert_eq!(pool.get_available_liquidity(), U256::ZERO);
+        assert_eq!(pool.loan_accruals.getter(U256::from(7)).rate_bps.get(), U32::from(1_000));
+    }
+
+    #[test]
+    fn repay_from_the_loan_manager_logs_the_loan() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let amount = U256::from(100_000_000u64);
+        let interest = U256::from(5_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+
+        vm.set_sender(LOAN_MANAGER);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(7), 1_000).unwrap();
+        let call = loan_manager_abi::repayCall { principal: amount, interest, loan_id: U256::from(7) };
+        let routed = route(&mut pool, &call.abi_encode()).expect("unknown selector");
+        assert_eq!(routed, Ok(Vec::new()));
+        assert_eq!(pool.get_available_liquidity(), amount);
+
+        let (topics, data) = vm.get_emitted_logs().pop().unwrap();
+        assert_eq!(topics[0], <Repaid as alloy_sol_types::SolEvent>::SIGNATURE_HASH);
+        assert_eq!(topics[1], stylus_sdk::alloy_primitives::B256::from(U256::from(7)));
+        assert_eq!(data, (amount, interest, U256::ZERO).abi_encode_params());
+    }
+
+    #[test]
+    fn projected_interest_survives_later_borrows() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount * U256::from(10));
+
+        let half_year = SECONDS_PER_MONTH * 6;
+        vm.set_block_timestamp(1_700_000_000);
+        vm.set_sender(LOAN_MANAGER);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(1), 1_000).unwrap();
+
+        // half a year at 10% on 100 USDC, untouched by the second draw
+        vm.set_block_timestamp(1_700_000_000 + half_year);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(2), 2_000).unwrap();
+        assert_eq!(pool.get_pending_interest(LENDER_A), U256::from(5_000_000u64));
+
+        // then both loans accrue at their own rates
+        vm.set_block_timestamp(1_700_000_000 + 2 * half_year);
+        assert_eq!(pool.get_pending_interest(LENDER_A), U256::from(20_000_000u64));
+    }
+
+    #[test]
+    fn fees_and_write_offs_leave_other_loans_projection() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount * U256::from(10));
+
+        let half_year = SECONDS_PER_MONTH * 6;
+        vm.set_block_timestamp(1_700_000_000);
+        vm.set_sender(LOAN_MANAGER);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(1), 1_000).unwrap();
+
+        // an origination fee repaid with the draw isn't interest loan 1 accrued
+        vm.set_block_timestamp(1_700_000_000 + half_year);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(2), 2_000).unwrap();
+        pool.repay(U256::ZERO, U256::from(1_000_000u64), U256::from(2)).unwrap();
+        assert_eq!(pool._projected_accrual(), U256::from(5_000_000u64));
+
+        // writing loan 2 off takes its half year at 20% with it
+        vm.set_block_timestamp(1_700_000_000 + 2 * half_year);
+        assert_eq!(pool._projected_accrual(), U256::from(20_000_000u64));
+        pool.write_off(U256::from(2), amount).unwrap();
+        assert_eq!(pool._projected_accrual(), U256::from(10_000_000u64));
+    }
+
+    #[test]
+    fn token_reentering_withdraw_is_refused() {
+        let (vm, mut pool) = setup();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+
+        // a token that calls back into withdraw while the pool is asking it
+        // for its balance, before any payout has been booked
+        let reentered = std::rc::Rc::new(std::cell::RefCell::new(None));
+        let (token_vm, seen) = (vm.clone(), reentered.clone());
+        let balance = token_abi::balanceOfCall { owner: vm.contract_address() }.abi_encode();
+        mock_call_with(
+            USDC,
+            balance,
+            Box::new(move || {
+                let mut pool = LendingPool::from(&token_vm);
+                *seen.borrow_mut() = Some(pool.withdraw(amount));
+                Ok(U256::MAX.abi_encode())
+            }),
+        );
+
+        vm.set_sender(LENDER_A);
+        pool.withdraw(amount).unwrap();
+        assert_eq!(*reentered.borrow(), Some(Err(b"Reentrant call".to_vec())));
+        assert_eq!(pool.get_total_liquidity(), U256::ZERO);
+        assert_eq!(pool.get_lender_info(LENDER_A).0, U256::ZERO);
+        assert!(!pool.locked.get());
+    }
+
+    #[test]
+    fn token_moving_calls_refuse_to_reenter() {
+        let (vm, mut pool) = setup();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+
+        // what a token calling back mid-transfer would find
+        pool.locked.set(true);
+        let reentrant = Err(b"Reentrant call".to_vec());
+        assert_eq!(pool.deposit(amount), reentrant);
+        vm.set_sender(LENDER_A);
+        assert_eq!(pool.withdraw(amount), reentrant);
+        vm.set_sender(LOAN_MANAGER);
+        assert_eq!(pool.borrow(amount, LOAN_MANAGER, U256::from(1), 1_000), reentrant);
+        assert_eq!(pool.repay(amount, U256::ZERO, U256::from(1)), reentrant);
+        vm.set_sender(ADMIN);
+        assert_eq!(pool.seed_deposits(vec![LENDER_B], vec![amount]), reentrant);
+
+        pool.locked.set(false);
+        pool.seed_deposits(vec![LENDER_B], vec![amount]).unwrap();
+        assert_eq!(pool.get_lender_info(LENDER_B).0, amount);
+        assert!(!pool.locked.get());
+    }
+
+    #[test]
+    fn interest_per_share_keeps_dust_and_survives_huge_repays() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let liquidity = U256::from(3) * ACC_PRECISION;
+        deposit(&vm, &mut pool, LENDER_A, liquidity);
+
+        // a single unit against a deep pool rounds to nothing per repay, but
+        // the remainder carries until it adds up
+        vm.set_sender(LOAN_MANAGER);
+        for expected in [0u64, 0, 1] {
+            pool.repay(U256::ZERO, U256::from(1), U256::from(1)).unwrap();
+            assert_eq!(pool.accumulated_interest_per_share.get(), U256::from(expected));
+        }
+        assert_eq!(pool.get_interest_since_last_claim(LENDER_A), U256::from(3));
+
+        // interest too large to scale first falls back to dividing first
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        deposit(&vm, &mut pool, LENDER_A, U256::from(2));
+        let interest = U256::MAX / ACC_PRECISION + U256::from(1);
+        vm.set_sender(LOAN_MANAGER);
+        pool.repay(U256::ZERO, interest, U256::from(1)).unwrap();
+        assert_eq!(
+            pool.accumulated_interest_per_share.get(),
+            interest / U256::from(2) * ACC_PRECISION
+        );
+    }
+
+    #[test]
+    fn last_lender_out_sweeps_residual_interest() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let (a, b) = (U256::from(100_000_000u64), U256::from(50_000_001u64));
+        deposit(&vm, &mut pool, LENDER_A, a);
+        deposit(&vm, &mut pool, LENDER_B, b);
+
+        // an amount that doesn't split evenly between the two
+        vm.set_sender(LOAN_MANAGER);
+        pool.repay(U256::ZERO, U256::from(1_000_001u64), U256::from(1)).unwrap();
+
+        vm.set_sender(LENDER_A);
+        pool.withdraw(a).unwrap();
+        assert!(pool.total_interest_earned.get() > U256::ZERO);
+        vm.set_sender(LENDER_B);
+        pool.withdraw(b).unwrap();
+
+        assert_eq!(pool.get_lender_count(), U256::ZERO);
+        assert_eq!(pool.get_total_liquidity(), U256::ZERO);
+        assert_eq!(pool.total_interest_earned.get(), U256::ZERO);
+        assert_eq!(pool.accumulated_interest_per_share.get(), U256::ZERO);
+        assert_eq!(pool.acc_interest_remainder.get(), U256::ZERO);
+    }
+
+    #[test]
+    fn share_percentage_is_clamped_after_a_write_off() {
+        let (vm, mut pool) = setup();
+        let amount = U256::from(100_000_000u64);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+        vm.set_sender(LOAN_MANAGER);
+        pool.borrow(amount, LOAN_MANAGER, U256::from(1), 1_000).unwrap();
+        pool.write_off(U256::from(1), amount).unwrap();
+
+        // A's deposit now dwarfs what is left in the pool
+        deposit(&vm, &mut pool, LENDER_B, amount / U256::from(2));
+        deposit(&vm, &mut pool, LENDER_A, U256::from(1));
+        let bps = U32::from(BPS_DENOMINATOR);
+        assert_eq!(pool.get_lender_info(LENDER_A).2, bps);
+        assert!(pool.get_lender_info(LENDER_B).2 <= bps);
+        assert_eq!(LendingPool::_share_bps(U256::MAX), bps);
+    }
+
+    #[test]
+    fn claiming_interest_leaves_the_principal_earning() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let amount = U256::from(100_000_000u64);
+        let interest = U256::from(1_000_000u64);
+        vm.set_block_timestamp(1_700_000_000);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+        deposit(&vm, &mut pool, LENDER_B, amount);
+        vm.set_sender(LOAN_MANAGER);
+        pool.repay(U256::ZERO, interest, U256::from(1)).unwrap();
+
+        vm.set_block_timestamp(1_700_000_000 + SECONDS_PER_MONTH);
+        vm.set_sender(LENDER_A);
+        let claimable = pool.get_claimable_interest(LENDER_A);
+        assert_eq!(claimable, interest / U256::from(2));
+        assert_eq!(pool.claim_interest().unwrap(), claimable);
+        assert_eq!(pool.get_claimable_interest(LENDER_A), U256::ZERO);
+        assert_eq!(pool.get_lender_info(LENDER_A).0, amount);
+        let deposited_at = pool.lenders.getter(LENDER_A).deposit_timestamp.get();
+        assert_eq!(deposited_at, U64::from(1_700_000_000));
+
+        // the full deposit keeps its cut of the next repay
+        vm.set_sender(LOAN_MANAGER);
+        pool.repay(U256::ZERO, interest, U256::from(1)).unwrap();
+        let expected = interest * amount / (amount * U256::from(2) + claimable);
+        let earned = pool.get_claimable_interest(LENDER_A);
+        assert!(earned.abs_diff(expected) <= U256::from(1));
+    }
+
+    #[test]
+    fn pool_events_decode() {
+        let (vm, mut pool) = setup();
+        pool.set_reserve_factor_bps(0).unwrap();
+        let amount = U256::from(100_000_000u64);
+        let interest = U256::from(1_000_000u64);
+        let loan_id = U256::from(7);
+        vm.set_block_timestamp(1_700_000_000);
+        deposit(&vm, &mut pool, LENDER_A, amount);
+
+        vm.set_sender(LOAN_MANAGER);
+        pool.borrow(amount, LENDER_B, loan_id, 1_000).unwrap();
+        let borrowed = last_event::<Borrowed>(&vm);
+        assert_eq!((borrowed.loan_id, borrowed.borrower, borrowed.amount), (loan_id, LENDER_B, amount));
+        pool.repay(amount / U256::from(2), interest, loan_id).unwrap();
+        let repaid = last_event::<Repaid>(&vm);
+        assert_eq!((repaid.loan_id, repaid.principal), (loan_id, amount / U256::from(2)));
+        assert_eq!((repaid.interest, repaid.reserve), (interest, U256::ZERO));
+        let accrued = last_event::<InterestAccrued>(&vm);
+        assert_eq!((accrued.amount, accrued.total_liquidity), (interest, amount));
+        assert_eq!(accrued.new_acc_per_share, pool.accumulated_interest_per_share.get());
+
+        vm.set_block_timestamp(1_700_000_000 + SECONDS_PER_MONTH);
+        vm.set_sender(LENDER_A);
+        let claimed = pool.claim_interest().unwrap();
+        let event = last_event::<InterestClaimed>(&vm);
+        assert_eq!((event.lender, event.amount), (LENDER_A, claimed));
 
-        // lender.last_acc_interest_per_share.set(acc);
-        let mut _kk = self.lenders.setter(lender_addr);
-        _kk.last_acc_interest_per_share.set(acc);
-        _kk.earned_interest.set(pending);
+        vm.set_sender(LOAN_MANAGER);
+        pool.write_off(loan_id, amount / U256::from(2)).unwrap();
+        let bad_debt = last_event::<BadDebtRecorded>(&vm);
+        assert_eq!((bad_debt.loan_id, bad_debt.loss), (loan_id, amount / U256::from(2)));
 
-        pending
+        vm.set_sender(ADMIN);
+        pool.pause_all().unwrap();
+        let token = Address::repeat_byte(0x0d);
+        let balance = token_abi::balanceOfCall { owner: vm.contract_address() }.abi_encode();
+        mock_call(token, balance, Ok(U256::MAX.abi_encode()));
+        pool.migrate_token(token).unwrap();
+        let migrated = last_event::<TokenMigrated>(&vm);
+        assert_eq!((migrated.old_token, migrated.new_token), (USDC, token));
     }
 }
@@ -1,29 +1,76 @@
 #![cfg_attr(not(any(test, feature = "export-abi")), no_main)]
 #![cfg_attr(not(any(test, feature = "export-abi")), no_std)]
+// export-abi chains one iterator per public function that returns
+// something, and this contract has more of them than the default limit allows
+#![recursion_limit = "512"]
 
 #[macro_use]
 extern crate alloc;
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 
-use alloy_sol_types::{sol, SolEvent};
+use alloy_sol_types::{sol, Eip712Domain, SolCall, SolStruct, SolValue};
 use stylus_sdk::{
-    alloy_primitives::{Address, U256, U32, U64, U8},
+    abi::Bytes,
+    alloy_primitives::{Address, FixedBytes, B256, U256, U32, U64, U8},
+    crypto::keccak,
     prelude::*,
     storage::{StorageU256, StorageVec},
+    stylus_core::{calls::context::Call, log},
 };
 
+#[cfg(test)]
+#[path = "../../test_hostio.rs"]
+mod test_hostio;
+
 sol_storage! {
     #[entrypoint]
     pub struct LoanManager {
         address admin;
         address oracle;
+        address pending_oracle;
+        address approval_authority;
         address remittance_nft;
         address lending_pool;
         address usdc;
         uint256 loan_counter;
+        uint32 duration_history_multiple;
+        bool paused;
+        uint32 reliability_decay_per_month;
+        uint32 max_ltv_bps;
+        uint32 safety_buffer_bps;
+        uint64 grace_period;
+        uint64 first_payment_grace;
+        uint64 liquidation_delay_seconds;
+        uint64 pending_expiry_seconds;
+        uint64 write_off_period;
+        uint64 compounding_period;
+        uint32 max_duration_months;
+        uint32 liquidation_ltv_threshold_bps;
+        uint32 min_aggregate_collateral_bps; // 0 disables the system-wide check
+        uint32 anomaly_multiple; // 0 disables the auto-repay anomaly check
+        uint8 payment_waterfall; // 0=interest then principal,1=principal then interest
+        bool round_interest_up;
+        uint32 origination_fee_bps;
+        uint32 min_rate_bps;
+        uint32 max_rate_bps;
+        uint256 keeper_reward; // paid per default, liquidation or undercollateralized flag
+        uint256 keeper_fund; // USDC held for keeper rewards
+        uint256 total_keeper_rewards;
+        mapping(address => uint256) keeper_earnings;
+        mapping(uint256 => uint8) keeper_rewarded; // per loan, one bit per action already paid
+        mapping(uint256 => uint64) missed_due; // per loan, the last due date counted as missed
         mapping(uint256 => Loan) loans;
         mapping(address => StorageVec<StorageU256>) borrower_loans;
+        StorageVec<StorageU256> all_loan_ids; // counter and deterministic ids, in creation order
+        // running totals over active loans, read by _aggregate_position
+        uint256 active_collateral_value;
+        uint256 active_outstanding;
+        mapping(uint256 => uint256) collateral_snapshot; // valuation counted in active_collateral_value
+        mapping(address => uint256) consent_nonces;
+        mapping(uint256 => address) nft_depositors;
+        mapping(uint256 => uint256) nft_to_loan;
+        mapping(uint256 => StorageVec<PaymentRecord>) loan_payments;
     }
 
     pub struct Loan {
@@ -38,17 +85,35 @@ sol_storage! {
         uint256 monthly_payment;
         uint64 start_timestamp;
         uint64 next_payment_due;
-        uint8 status;             // 0=Pending,1=Active,2=Repaid,3=Defaulted
+        // 0=Pending,1=Active,2=Repaid,3=Defaulted,4=Liquidated,5=WrittenOff,6=Cancelled
+        uint8 status;
         uint32 payments_made;
         uint32 payments_missed;
+        uint32 interest_only_months;
+        uint32 interest_only_remaining;
+        bytes32 external_ref;
+        uint64 defaulted_at;
+        bool auto_repay_enabled;
+        uint256 current_period_paid;
+        uint64 current_period_due; // next_payment_due that current_period_paid counts toward
+        uint64 last_compounded_at;
+        uint256 capitalized_interest; // part of outstanding_balance that is compounded interest
+        uint64 undercollateralized_at; // 0 unless flagged by check_and_flag_undercollateralized
+        string agreement_uri;
+        uint256 disbursed_amount; // loan_amount less the origination fee, set on approval
+        uint256 avg_remittance; // rolling average of remittances seen by auto-repay
+        uint256 held_remittance; // anomalous remittance awaiting admin review
     }
-}
 
-sol_interface! {
-    interface IERC20 {
-        function transferFrom(address from, address to, uint256 value) external returns (bool);
+    pub struct PaymentRecord {
+        uint64 timestamp;
+        uint256 amount;
+        uint256 principal;
+        uint256 interest;
     }
+}
 
+sol_interface! {
     interface IRemittanceNFT {
         function getRemittance(uint256 token_id)
             external
@@ -56,21 +121,74 @@ sol_interface! {
             returns (address, uint256, uint256, uint256, bool);
         function stakeNFT(uint256 token_id, uint256 loan_id) external;
         function unstakeNFT(uint256 token_id) external;
+        function transferFrom(address from, address to, uint256 token_id) external;
     }
 
     interface ILendingPool {
-        function borrow(uint256 amount, address borrower, uint256 loan_id) external;
+        function borrow(uint256 amount, address borrower, uint256 loan_id, uint32 rate_bps) external;
         function repay(uint256 principal, uint256 interest, uint256 loan_id) external;
+        function writeOff(uint256 loan_id, uint256 loss) external;
+        function getTotalLiquidity() external view returns (uint256);
+        function getAvailableLiquidity() external view returns (uint256);
+        function getLenderInfo(address lender)
+            external
+            view
+            returns (uint256, uint256, uint32, uint256);
+    }
+
+    interface IOracleVerifier {
+        function getLastRemittanceAt(uint256 nft_id) external view returns (uint64);
     }
 }
 
 sol! {
-    event LoanRequested(address indexed borrower, uint256 indexed loan_id);
+    // encoded by hand so tokens that return nothing (USDT) still work
+    function transfer(address to, uint256 value) external returns (bool);
+    function transferFrom(address from, address to, uint256 value) external returns (bool);
+
+    event LoanRequested(address indexed borrower, uint256 indexed loan_id, bytes32 external_ref);
     event LoanApproved(uint256 indexed loan_id);
     event PaymentMade(uint256 indexed loan_id, uint256 amount);
     event PaymentMissed(uint256 indexed loan_id, uint32 missed_count);
+    event LoanStatusChanged(uint256 indexed loan_id, uint8 status);
+    event LoanLiquidated(uint256 indexed loan_id, uint256 outstanding);
+    event LoanCured(uint256 indexed loan_id);
+    event LoanExpired(uint256 indexed loan_id);
+    event LoanWrittenOff(uint256 indexed loan_id, uint256 loss);
+    event LoanTermExtended(uint256 indexed loan_id, uint32 additional_months);
+    event LoanUndercollateralized(uint256 indexed loan_id, uint256 ltv);
+    event AnomalousRemittanceBlocked(uint256 indexed loan_id, uint256 amount);
+    event TokenMigrated(address indexed old_token, address indexed new_token);
+    event OracleProposed(address indexed oracle);
+    event OracleAccepted(address indexed oracle);
+    event ApprovalAuthorityChanged(address indexed authority);
+    // action: 0=default,1=liquidation,2=undercollateralized flag
+    event KeeperRewarded(address indexed keeper, uint8 action, uint256 amount);
+
+    struct LoanConsent {
+        address borrower;
+        uint256 nft_id;
+        uint256 amount;
+        uint32 duration_months;
+        uint32 interest_only_months;
+        uint256 nonce;
+        uint64 deadline;
+    }
 }
 
+const BPS_DENOMINATOR: u32 = 10_000;
+const MONTHS_PER_YEAR: u32 = 12;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// average Gregorian month, so MONTHS_PER_YEAR of them make exactly one year;
+// used to annualize rates and age remittances, never for due dates
+const SECONDS_PER_MONTH: u64 = 2_629_746;
+// one installment: the first due date, every roll of next_payment_due and
+// the expected completion are all counted in these
+const PAYMENT_PERIOD: u64 = 30 * SECONDS_PER_DAY;
+
+// bytes4(keccak256("onERC721Received(address,address,uint256,bytes)"))
+const ERC721_RECEIVED: [u8; 4] = [0x15, 0x0b, 0x7a, 0x02];
+
 #[public]
 impl LoanManager {
     #[constructor]
@@ -79,8 +197,21 @@ impl LoanManager {
             return Err(b"Already initialized".to_vec());
         }
         self.admin.set(self.vm().msg_sender());
+        self.approval_authority.set(self.vm().msg_sender());
         self.usdc.set(usdc);
         self.loan_counter.set(U256::ZERO);
+        self.duration_history_multiple.set(U32::from(2));
+        self.reliability_decay_per_month.set(U32::from(5));
+        self.max_ltv_bps.set(U32::from(5000)); // 50%
+        self.safety_buffer_bps.set(U32::from(500)); // 5%
+        self.grace_period.set(U64::from(7 * SECONDS_PER_DAY));
+        self.first_payment_grace.set(U64::from(14 * SECONDS_PER_DAY));
+        self.liquidation_delay_seconds.set(U64::from(14 * SECONDS_PER_DAY));
+        self.pending_expiry_seconds.set(U64::from(30 * SECONDS_PER_DAY));
+        self.write_off_period.set(U64::from(180 * SECONDS_PER_DAY));
+        self.max_rate_bps.set(U32::from(BPS_DENOMINATOR));
+        self.liquidation_ltv_threshold_bps.set(U32::from(BPS_DENOMINATOR)); // 100%
+        self.anomaly_multiple.set(U32::from(5));
         Ok(())
     }
 
@@ -93,253 +224,2940 @@ impl LoanManager {
         if self.admin.get() != self.vm().msg_sender() {
             return Err(b"Only admin".to_vec());
         }
+        // the oracle is wired here once; after that it moves via propose_oracle
+        let current = self.oracle.get();
+        if current != Address::ZERO && oracle != current {
+            return Err(b"Use propose_oracle".to_vec());
+        }
         self.remittance_nft.set(remittance_nft);
         self.lending_pool.set(lending_pool);
         self.oracle.set(oracle);
         Ok(())
     }
 
-    pub fn request_loan(
-        &mut self,
-        nft_id: U256,
-        amount: U256,
-        duration_months: u32,
-    ) -> Result<U256, Vec<u8>> {
-        let borrower = self.vm().msg_sender();
-
-        // let (owner, _, reliability_score, _, _) = IRemittanceNFT::new(self.remittance_nft.get())
-        // .getRemittance(nft_id);
-        let remittance_nft = IRemittanceNFT::new(self.remittance_nft.get());
-        let (owner, _, reliability_score, _, _) =
-            remittance_nft.get_remittance(&mut *self, nft_id)?;
-
-        if owner != borrower {
-            return Err(b"NFT does not belong to borrower".to_vec());
+    // Two-step oracle change: the new oracle only takes over once it calls
+    // accept_oracle itself, so a dead or wrong address can't be wired in.
+    pub fn propose_oracle(&mut self, oracle: Address) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        self.pending_oracle.set(oracle);
+        log(self.vm(), OracleProposed { oracle });
+        Ok(())
+    }
 
-        let interest_rate_bps = Self::_calculate_interest_rate(reliability_score);
-        let monthly_payment =
-            Self::_calculate_monthly_payment(amount, interest_rate_bps, duration_months);
-        let current_time = U64::from(self.vm().block_timestamp());
-        let next_pay_date = U64::from(
-            self.vm()
-                .block_timestamp()
-                .saturating_add(30 * 24 * 60 * 60),
-        );
+    pub fn accept_oracle(&mut self) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        if sender == Address::ZERO || sender != self.pending_oracle.get() {
+            return Err(b"Only pending oracle".to_vec());
+        }
+        self.oracle.set(sender);
+        self.pending_oracle.set(Address::ZERO);
+        log(self.vm(), OracleAccepted { oracle: sender });
+        Ok(())
+    }
 
-        let loan_id = self.loan_counter.get() + U256::from(1u64);
-        self.loan_counter.set(loan_id);
+    // (oracle, pending_oracle)
+    pub fn get_oracle(&self) -> (Address, Address) {
+        (self.oracle.get(), self.pending_oracle.get())
+    }
 
-        let mut loan = self.loans.setter(loan_id);
-        loan.loan_id.set(loan_id);
-        loan.borrower.set(borrower);
-        loan.nft_collateral_id.set(nft_id);
-        loan.loan_amount.set(amount);
-        loan.outstanding_balance.set(amount);
-        loan.total_repaid.set(U256::ZERO);
-        loan.interest_rate_bps.set(U32::from(interest_rate_bps));
-        loan.duration_months.set(U32::from(duration_months));
-        loan.monthly_payment.set(monthly_payment);
-        loan.start_timestamp.set(current_time);
-        loan.next_payment_due.set(next_pay_date);
-        loan.status.set(U8::from(0));
-        loan.payments_made.set(U32::from(0));
-        loan.payments_missed.set(U32::from(0));
+    pub fn set_duration_history_multiple(&mut self, multiple: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if multiple == 0 {
+            return Err(b"Invalid multiple".to_vec());
+        }
+        self.duration_history_multiple.set(U32::from(multiple));
+        Ok(())
+    }
 
-        // self.loans.insert(loan_id, loan);
+    pub fn get_duration_history_multiple(&self) -> U32 {
+        self.duration_history_multiple.get()
+    }
 
-        // let mut list = self.borrower_loans.get(borrower);
-        // list.push(loan_id);
-        // self.borrower_loans.insert(borrower, list);
+    pub fn set_reliability_decay_per_month(&mut self, points: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.reliability_decay_per_month.set(U32::from(points));
+        Ok(())
+    }
 
-        let req_loan = LoanRequested { borrower, loan_id };
-        self.vm().emit_log(&req_loan.encode_data(), 2);
-        Ok(loan_id)
+    pub fn get_reliability_decay_per_month(&self) -> U32 {
+        self.reliability_decay_per_month.get()
     }
 
-    pub fn approve_loan(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
-        if self.vm().msg_sender() != self.admin.get() {
+    pub fn set_max_ltv_bps(&mut self, max_ltv_bps: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
             return Err(b"Only admin".to_vec());
         }
-
-        let loan = self.loans.getter(loan_id);
-        let loan_amount = loan.loan_amount.get();
-        let borrower = loan.borrower.get();
-        let nft_id = loan.nft_collateral_id.get();
-        if loan.status.get() != U8::from(0) {
-            return Err(b"Loan not pending".to_vec());
+        if max_ltv_bps == 0 || max_ltv_bps > BPS_DENOMINATOR {
+            return Err(b"Invalid LTV".to_vec());
         }
+        if U32::from(max_ltv_bps) <= self.safety_buffer_bps.get() {
+            return Err(b"LTV below safety buffer".to_vec());
+        }
+        self.max_ltv_bps.set(U32::from(max_ltv_bps));
+        Ok(())
+    }
 
-        {
-            let _ = IRemittanceNFT::new(self.remittance_nft.get())
-                .stake_nft(&mut *self, nft_id, loan_id)?;
+    pub fn get_max_ltv_bps(&self) -> U32 {
+        self.max_ltv_bps.get()
+    }
 
-            let _ = ILendingPool::new(self.lending_pool.get()).borrow(
-                &mut *self,
-                loan_amount,
-                borrower,
-                loan_id,
-            )?;
+    pub fn set_safety_buffer_bps(&mut self, buffer_bps: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        if U32::from(buffer_bps) >= self.max_ltv_bps.get() {
+            return Err(b"Buffer exceeds LTV".to_vec());
+        }
+        self.safety_buffer_bps.set(U32::from(buffer_bps));
+        Ok(())
+    }
 
-        {
-            let mut loan = self.loans.setter(loan_id);
-            loan.status.set(U8::from(1));
+    pub fn get_safety_buffer_bps(&self) -> U32 {
+        self.safety_buffer_bps.get()
+    }
+
+    pub fn set_payment_waterfall(&mut self, waterfall: u8) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        if waterfall > 1 {
+            return Err(b"Invalid waterfall".to_vec());
+        }
+        self.payment_waterfall.set(U8::from(waterfall));
+        Ok(())
+    }
 
-        let approve_loan = LoanApproved { loan_id };
-        self.vm().emit_log(&approve_loan.encode_data(), 1);
+    pub fn get_payment_waterfall(&self) -> U8 {
+        self.payment_waterfall.get()
+    }
+
+    // Rounding interest up charges at most one extra base unit per payment,
+    // which is 10000 / interest bps of a payment carrying `interest` units
+    // (e.g. 1 bp on 10000 units). The default rounds down, for the borrower.
+    pub fn set_round_interest_up(&mut self, round_up: bool) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.round_interest_up.set(round_up);
         Ok(())
     }
 
-    pub fn make_payment(&mut self, loan_id: U256, amount: U256) -> Result<(), Vec<u8>> {
-        let sender = self.vm().msg_sender();
-        let loan = self.loans.getter(loan_id);
-        if loan.status.get() != U8::from(1) {
-            return Err(b"Loan not active".to_vec());
+    pub fn get_round_interest_up(&self) -> bool {
+        self.round_interest_up.get()
+    }
+
+    pub fn set_origination_fee_bps(&mut self, fee_bps: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
-        if sender != loan.borrower.get() {
-            return Err(b"Only borrower can pay".to_vec());
+        if fee_bps >= BPS_DENOMINATOR {
+            return Err(b"Invalid origination fee".to_vec());
         }
-        self._process_payment(loan_id, sender, amount)
+        self.origination_fee_bps.set(U32::from(fee_bps));
+        Ok(())
     }
 
-    pub fn process_auto_repayment(
-        &mut self,
-        loan_id: U256,
-        remittance_amount: U256,
-    ) -> Result<U256, Vec<u8>> {
-        if self.vm().msg_sender() != self.oracle.get() {
-            return Err(b"Only oracle".to_vec());
+    pub fn get_origination_fee_bps(&self) -> U32 {
+        self.origination_fee_bps.get()
+    }
+
+    // fee held back from a disbursement of `amount`, before any loyalty discount
+    pub fn get_origination_fee(&self, amount: U256) -> U256 {
+        amount * U256::from(self.origination_fee_bps.get()) / U256::from(BPS_DENOMINATOR)
+    }
+
+    // every rate a loan is given is clamped into [min_rate_bps, max_rate_bps]
+    pub fn set_rate_bounds(&mut self, min_rate_bps: u32, max_rate_bps: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
-        let loan = self.loans.getter(loan_id);
-        let payment_amount = if remittance_amount >= loan.monthly_payment.get() {
-            loan.monthly_payment.get()
-        } else {
-            remittance_amount
-        };
-        self._process_payment(loan_id, loan.borrower.get(), payment_amount)?;
-        Ok(remittance_amount - payment_amount)
+        if min_rate_bps > max_rate_bps {
+            return Err(b"Invalid rate bounds".to_vec());
+        }
+        self.min_rate_bps.set(U32::from(min_rate_bps));
+        self.max_rate_bps.set(U32::from(max_rate_bps));
+        Ok(())
     }
 
-    // ---- Mark payment missed ----
-    pub fn mark_payment_missed(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
-        if self.vm().msg_sender() != self.oracle.get() {
-            return Err(b"Only oracle".to_vec());
+    // (min_rate_bps, max_rate_bps)
+    pub fn get_rate_bounds(&self) -> (U32, U32) {
+        (self.min_rate_bps.get(), self.max_rate_bps.get())
+    }
+
+    pub fn set_grace_period(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        self.grace_period.set(U64::from(seconds));
+        Ok(())
+    }
 
-        let mut loan = self.loans.setter(loan_id);
-        let missed = loan.payments_missed.get().saturating_add(U32::from(1));
-        loan.payments_missed.set(missed);
+    pub fn get_grace_period(&self) -> U64 {
+        self.grace_period.get()
+    }
 
-        if missed >= U32::from(2u64) {
-            loan.status.set(U8::from(3)); // Defaulted
+    // Grace on a loan's first installment only. It never ends up tighter
+    // than grace_period, which covers the first payment too if it's longer.
+    pub fn set_first_payment_grace(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        self.first_payment_grace.set(U64::from(seconds));
+        Ok(())
+    }
 
+    pub fn get_first_payment_grace(&self) -> U64 {
+        self.first_payment_grace.get()
+    }
+
+    // Seconds between compoundings of overdue interest, 0 (the default) keeps
+    // interest simple. Once a due date passes unpaid, each period adds that
+    // period's interest to the balance, so later interest is charged on
+    // interest too: the loan's effective APY then ends up above its quoted
+    // APR, more so the shorter the period and the longer it stays behind.
+    pub fn set_compounding_period(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.compounding_period.set(U64::from(seconds));
         Ok(())
     }
 
-    fn _process_payment(
-        &mut self,
-        loan_id: U256,
-        payer: Address,
-        amount: U256,
-    ) -> Result<(), Vec<u8>> {
-        if amount == U256::ZERO {
-            return Err(b"Amount must be > 0".to_vec());
+    pub fn get_compounding_period(&self) -> U64 {
+        self.compounding_period.get()
+    }
+
+    // lets a keeper bring an overdue loan's balance up to date
+    pub fn compound_interest(&mut self, loan_id: U256) -> Result<U256, Vec<u8>> {
+        if self.loans.getter(loan_id).status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
         }
+        Ok(self._compound_interest(loan_id))
+    }
 
-        let lending_pool = self.lending_pool.get();
-        let remittance_nft_addr = self.remittance_nft.get();
-        let usdc = self.usdc.get();
+    pub fn is_payment_overdue(&self, loan_id: U256) -> bool {
         let loan = self.loans.getter(loan_id);
-        let outstanding = loan.outstanding_balance.get();
-        let interest_rate_bps = loan.interest_rate_bps.get();
-        let nft_id = loan.nft_collateral_id.get();
-        let interest_portion = Self::_calculate_interest_portion(outstanding, interest_rate_bps);
-        let total_repaid = loan.total_repaid.get();
-        let payments_made = loan.payments_made.get();
-        let next_payment_due = loan.next_payment_due.get();
-        // let payments_missed = loan.payments_missed.get();
-        let status = loan.status.get();
+        loan.status.get() == U8::from(1)
+            && U64::from(self.vm().block_timestamp())
+                > loan.next_payment_due.get().saturating_add(self._payment_grace(loan_id))
+    }
 
-        if status != U8::from(1) {
-            return Err(b"Loan not active".to_vec());
+    // Keeper helper over loans [start, start + limit) of the creation-order
+    // index (see get_loan_count): (active loans past due plus grace,
+    // defaulted loans whose collateral is still staked).
+    pub fn get_actionable_loans(
+        &self,
+        start: U256,
+        limit: U256,
+    ) -> Result<(Vec<U256>, Vec<U256>), Vec<u8>> {
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let mut overdue = Vec::new();
+        let mut liquidatable = Vec::new();
+
+        for loan_id in self._loan_id_page(start, limit) {
+            if self.is_payment_overdue(loan_id) {
+                overdue.push(loan_id);
+            } else {
+                let loan = self.loans.getter(loan_id);
+                if loan.status.get() == U8::from(3) {
+                    let (_, _, _, _, staked) =
+                        nft.get_remittance(self, loan.nft_collateral_id.get())?;
+                    if staked {
+                        liquidatable.push(loan_id);
+                    }
+                }
+            }
         }
 
-        let mut principal_portion = if amount > interest_portion {
-            amount - interest_portion
+        Ok((overdue, liquidatable))
+    }
+
+    // What's still owed toward the installment due at next_payment_due.
+    pub fn get_current_shortfall(&self, loan_id: U256) -> U256 {
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return U256::ZERO;
+        }
+        let paid = if loan.current_period_due.get() == loan.next_payment_due.get() {
+            loan.current_period_paid.get()
         } else {
             U256::ZERO
         };
+        loan.monthly_payment.get().saturating_sub(paid)
+    }
 
-        // ERC20 Transfer
-        {
-            let erc20 = IERC20::new(usdc);
-            erc20.transfer_from(&mut *self, payer, lending_pool, amount)?;
+    // Interest the active loans among [start, start + limit) of the index
+    // accrue this month, paid or not.
+    pub fn get_portfolio_accrued_interest(&self, start: U256, limit: U256) -> U256 {
+        let round_up = self.round_interest_up.get();
+        let mut total = U256::ZERO;
+
+        for loan_id in self._loan_id_page(start, limit) {
+            let loan = self.loans.getter(loan_id);
+            if loan.status.get() == U8::from(1) {
+                total += Self::_calculate_interest_portion(
+                    loan.outstanding_balance.get(),
+                    loan.interest_rate_bps.get(),
+                    round_up,
+                );
+            }
         }
+        total
+    }
 
-        {
-            let pool = ILendingPool::new(lending_pool);
-            pool.repay(&mut *self, principal_portion, interest_portion, loan_id)?;
+    // Principal still on the pool's books for loans [start, start + limit)
+    // of the index: active, defaulted and liquidated loans, less capitalized
+    // interest. Summed over the whole index it should match the pool's
+    // total_borrowed.
+    pub fn get_total_active_principal(&self, start: U256, limit: U256) -> U256 {
+        let mut total = U256::ZERO;
+        for loan_id in self._loan_id_page(start, limit) {
+            let loan = self.loans.getter(loan_id);
+            let status = loan.status.get();
+            if status == U8::from(1) || status == U8::from(3) || status == U8::from(4) {
+                total += loan.outstanding_balance.get() - loan.capitalized_interest.get();
+            }
         }
+        total
+    }
 
-        if principal_portion >= outstanding {
-            principal_portion = outstanding;
+    // Every loan ever created, counter and deterministic ids alike. The
+    // range views page through this index by position.
+    pub fn get_loan_count(&self) -> U256 {
+        U256::from(self.all_loan_ids.len())
+    }
 
-            let nft: IRemittanceNFT = IRemittanceNFT::new(remittance_nft_addr);
-            let _ = nft.unstake_nft(&mut *self, nft_id)?;
-        } else {
-        }
+    // loan id at `index` in creation order, zero past the end
+    pub fn get_loan_id_at(&self, index: U256) -> U256 {
+        self.all_loan_ids.get(index).unwrap_or(U256::ZERO)
+    }
 
-        {
-            let mut loan = self.loans.setter(loan_id);
-            loan.total_repaid.set(total_repaid);
-            loan.payments_made.set(payments_made);
-            loan.next_payment_due.set(next_payment_due);
+    // the NFT's recorded score, before any staleness decay
+    pub fn get_borrower_reliability(&self, nft_id: U256) -> Result<U256, Vec<u8>> {
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (_, _, reliability_score, _, _) = nft.get_remittance(self, nft_id)?;
+        Ok(reliability_score)
+    }
 
-            if principal_portion >= outstanding {
-                loan.outstanding_balance.set(U256::ZERO);
-                loan.status.set(U8::from(2)); // 2 = Fully repaid or closed
-            } else {
-                loan.outstanding_balance
-                    .set(outstanding - principal_portion);
-            }
+    pub fn get_effective_reliability(&self, nft_id: U256) -> Result<U256, Vec<u8>> {
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (_, _, reliability_score, _, _) = nft.get_remittance(self, nft_id)?;
+        self._effective_reliability(nft_id, reliability_score)
+    }
+
+    pub fn pause(&mut self) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        self.paused.set(true);
+        Ok(())
+    }
 
-        // Emit event
-        let event = PaymentMade { loan_id, amount };
-        self.vm().emit_log(&event.encode_data(), 2);
+    // Follows the pool onto a replacement token. Only while paused and with
+    // nothing lent out, so no loan is left owing in the old token.
+    pub fn migrate_token(&mut self, new_token: Address) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if !self.paused.get() {
+            return Err(b"Not paused".to_vec());
+        }
+        let old_token = self.usdc.get();
+        if new_token == Address::ZERO || new_token == old_token {
+            return Err(b"Invalid token".to_vec());
+        }
+        let pool = ILendingPool::new(self.lending_pool.get());
+        let total_liquidity = pool.get_total_liquidity(&*self)?;
+        let available = pool.get_available_liquidity(&*self)?;
+        if total_liquidity > available {
+            return Err(b"Loans outstanding".to_vec());
+        }
+        if self.keeper_fund.get() > U256::ZERO {
+            return Err(b"Keeper fund not empty".to_vec());
+        }
 
+        self.usdc.set(new_token);
+        log(self.vm(), TokenMigrated { old_token, new_token });
         Ok(())
     }
 
-    fn _calculate_interest_rate(score: U256) -> u32 {
-        let s = (score % U256::from(100u64)).to::<u64>();
-        if s >= 90 {
-            1500
-        } else if s >= 80 {
-            2000
-        } else if s >= 70 {
-            3000
-        } else {
-            4000
+    pub fn unpause(&mut self) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
         }
+        self.paused.set(false);
+        Ok(())
     }
 
-    fn _calculate_monthly_payment(principal: U256, rate_bps: u32, months: u32) -> U256 {
-        let total_interest =
-            principal * U256::from(rate_bps) * U256::from(months) / U256::from(12u64 * 10000u64);
-        let total = principal + total_interest;
-        if months == 0 {
+    pub fn can_approve(&self, who: Address) -> bool {
+        who == self.approval_authority.get()
+    }
+
+    // day-to-day approvals, kept apart from the admin key
+    pub fn set_approval_authority(&mut self, authority: Address) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.approval_authority.set(authority);
+        log(self.vm(), ApprovalAuthorityChanged { authority });
+        Ok(())
+    }
+
+    pub fn get_approval_authority(&self) -> Address {
+        self.approval_authority.get()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.get()
+    }
+
+    pub fn request_loan(
+        &mut self,
+        nft_id: U256,
+        amount: U256,
+        duration_months: u32,
+        interest_only_months: u32,
+        external_ref: B256,
+        auto_repay: bool,
+    ) -> Result<U256, Vec<u8>> {
+        let borrower = self.vm().msg_sender();
+        let loan_id = self._create_loan(
+            U256::ZERO,
+            borrower,
+            nft_id,
+            amount,
+            duration_months,
+            interest_only_months,
+            external_ref,
+        )?;
+        self.loans.setter(loan_id).auto_repay_enabled.set(auto_repay);
+        Ok(loan_id)
+    }
+
+    // Same as request_loan, but the id is keccak(borrower, nft_id, salt) so it
+    // can be known before the transaction lands. These ids sit outside the
+    // 1..=loan_counter range, but are in the index get_loan_id_at walks.
+    #[allow(clippy::too_many_arguments)]
+    pub fn request_loan_deterministic(
+        &mut self,
+        nft_id: U256,
+        amount: U256,
+        duration_months: u32,
+        interest_only_months: u32,
+        external_ref: B256,
+        salt: B256,
+        auto_repay: bool,
+    ) -> Result<U256, Vec<u8>> {
+        let borrower = self.vm().msg_sender();
+        let loan_id = Self::_deterministic_loan_id(borrower, nft_id, salt);
+        if self.loans.getter(loan_id).borrower.get() != Address::ZERO {
+            return Err(b"Loan id already used".to_vec());
+        }
+        self._create_loan(
+            loan_id,
+            borrower,
+            nft_id,
+            amount,
+            duration_months,
+            interest_only_months,
+            external_ref,
+        )?;
+        self.loans.setter(loan_id).auto_repay_enabled.set(auto_repay);
+        Ok(loan_id)
+    }
+
+    pub fn compute_loan_id(&self, borrower: Address, nft_id: U256, salt: B256) -> U256 {
+        Self::_deterministic_loan_id(borrower, nft_id, salt)
+    }
+
+    // Admin-originated loan; the borrower must have signed the terms as an
+    // EIP-712 LoanConsent for this contract.
+    #[allow(clippy::too_many_arguments)]
+    pub fn originate_loan_with_consent(
+        &mut self,
+        borrower: Address,
+        nft_id: U256,
+        amount: U256,
+        duration_months: u32,
+        interest_only_months: u32,
+        deadline: u64,
+        external_ref: B256,
+        borrower_signature: Bytes,
+    ) -> Result<U256, Vec<u8>> {
+        if self.vm().msg_sender() != self.admin.get() {
+            return Err(b"Only admin".to_vec());
+        }
+        if self.vm().block_timestamp() > deadline {
+            return Err(b"Consent expired".to_vec());
+        }
+
+        let nonce = self.consent_nonces.get(borrower);
+        let consent = LoanConsent {
+            borrower,
+            nft_id,
+            amount,
+            duration_months,
+            interest_only_months,
+            nonce,
+            deadline,
+        };
+        let digest = consent.eip712_signing_hash(&self._eip712_domain());
+        if self._ecrecover(digest, &borrower_signature)? != borrower {
+            return Err(b"Invalid borrower signature".to_vec());
+        }
+        self.consent_nonces.insert(borrower, nonce + U256::from(1u64));
+
+        self._create_loan(
+            U256::ZERO,
+            borrower,
+            nft_id,
+            amount,
+            duration_months,
+            interest_only_months,
+            external_ref,
+        )
+    }
+
+    // Sending a remittance NFT here with safeTransferFrom opens a loan for the
+    // sender in one step. data is abi.encode(uint256 amount,
+    // uint32 duration_months, uint32 interest_only_months, bytes32 external_ref).
+    // The NFT is held here and goes back to the sender when it's released.
+    #[selector(name = "onERC721Received")]
+    pub fn on_erc721_received(
+        &mut self,
+        _operator: Address,
+        from: Address,
+        token_id: U256,
+        data: Bytes,
+    ) -> Result<FixedBytes<4>, Vec<u8>> {
+        if self.vm().msg_sender() != self.remittance_nft.get() {
+            return Err(b"Unrecognized NFT".to_vec());
+        }
+        let (amount, duration_months, interest_only_months, external_ref) =
+            <(U256, u32, u32, B256)>::abi_decode_params(&data, true)
+                .map_err(|_| b"Invalid loan data".to_vec())?;
+
+        self.nft_depositors.insert(token_id, from);
+        self._create_loan(
+            U256::ZERO,
+            from,
+            token_id,
+            amount,
+            duration_months,
+            interest_only_months,
+            external_ref,
+        )?;
+        Ok(FixedBytes(ERC721_RECEIVED))
+    }
+
+    pub fn get_consent_nonce(&self, borrower: Address) -> U256 {
+        self.consent_nonces.get(borrower)
+    }
+
+    pub fn approve_loan(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        if !self.can_approve(self.vm().msg_sender()) {
+            return Err(b"Only approval authority".to_vec());
+        }
+        if self.paused.get() {
+            return Err(b"Paused".to_vec());
+        }
+
+        let loan = self.loans.getter(loan_id);
+        let loan_amount = loan.loan_amount.get();
+        let borrower = loan.borrower.get();
+        let nft_id = loan.nft_collateral_id.get();
+        if loan.status.get() != U8::from(0) {
+            return Err(b"Loan not pending".to_vec());
+        }
+
+        // With a fee the full amount is drawn here, the borrower gets the rest
+        // and the fee goes back to the pool as interest. The loan still owes
+        // the full loan_amount.
+        let fee = self._discounted_fee(self.get_origination_fee(loan_amount), borrower);
+        let recipient = if fee == U256::ZERO {
+            borrower
+        } else {
+            self.vm().contract_address()
+        };
+
+        if !self._aggregate_allows(nft_id, loan_amount)? {
+            return Err(b"Aggregate collateral too low".to_vec());
+        }
+
+        // Collateral is staked before any funds move. No manual unstake is
+        // needed if the borrow fails: returning the error reverts the whole
+        // call, the stake included, and the loan stays Pending for a retry.
+        {
+            IRemittanceNFT::new(self.remittance_nft.get())
+                .stake_nft(&mut *self, nft_id, loan_id)?;
+
+            let rate_bps = self.loans.getter(loan_id).interest_rate_bps.get().to::<u32>();
+            let borrowed = ILendingPool::new(self.lending_pool.get()).borrow(
+                &mut *self,
+                loan_amount,
+                recipient,
+                loan_id,
+                rate_bps,
+            ).map_err(Vec::<u8>::from);
+            match borrowed {
+                Err(data) if data == b"Insufficient liquidity" => {
+                    return Err(b"Approval failed: pool liquidity".to_vec());
+                }
+                other => other?,
+            }
+        }
+
+        if fee > U256::ZERO {
+            let lending_pool = self.lending_pool.get();
+            self._safe_transfer(borrower, loan_amount - fee)?;
+            self._safe_transfer(lending_pool, fee)?;
+            ILendingPool::new(lending_pool).repay(&mut *self, U256::ZERO, fee, loan_id)?;
+        }
+
+        {
+            let mut loan = self.loans.setter(loan_id);
+            loan.status.set(U8::from(1));
+            loan.disbursed_amount.set(loan_amount - fee);
+        }
+        self._enter_aggregate(loan_id)?;
+
+        let approve_loan = LoanApproved { loan_id };
+        log(self.vm(), approve_loan);
+        Ok(())
+    }
+
+    // Dry run of approve_loan's preconditions: (would succeed, the bytes it
+    // would revert with otherwise).
+    pub fn preview_approval(&self, loan_id: U256) -> (bool, Bytes) {
+        let fail = |reason: &[u8]| (false, Bytes::from(reason.to_vec()));
+        if self.paused.get() {
+            return fail(b"Paused");
+        }
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(0) {
+            return fail(b"Loan not pending");
+        }
+        let borrower = loan.borrower.get();
+        let nft_id = loan.nft_collateral_id.get();
+
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let Ok((owner, _, _, _, staked)) = nft.get_remittance(self, nft_id) else {
+            return fail(b"NFT lookup failed");
+        };
+        let deposited = owner == self.vm().contract_address()
+            && self.nft_depositors.get(nft_id) == borrower;
+        if owner != borrower && !deposited {
+            return fail(b"NFT does not belong to borrower");
+        }
+        if staked {
+            return fail(b"NFT already staked");
+        }
+
+        let pool = ILendingPool::new(self.lending_pool.get());
+        let Ok(available) = pool.get_available_liquidity(self) else {
+            return fail(b"Pool lookup failed");
+        };
+        if available < loan.loan_amount.get() {
+            return fail(b"Approval failed: pool liquidity");
+        }
+        match self._aggregate_allows(nft_id, loan.loan_amount.get()) {
+            Ok(true) => {}
+            Ok(false) => return fail(b"Aggregate collateral too low"),
+            Err(_) => return fail(b"NFT lookup failed"),
+        }
+        (true, Bytes::from(Vec::new()))
+    }
+
+    pub fn set_pending_expiry(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.pending_expiry_seconds.set(U64::from(seconds));
+        Ok(())
+    }
+
+    pub fn get_pending_expiry(&self) -> U64 {
+        self.pending_expiry_seconds.get()
+    }
+
+    // Anyone can cancel a request that sat unapproved past the expiry; an NFT
+    // sent in for it goes back to the borrower.
+    pub fn expire_pending_loan(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        let loan = self.loans.getter(loan_id);
+        let nft_id = loan.nft_collateral_id.get();
+        if loan.borrower.get() == Address::ZERO || loan.status.get() != U8::from(0) {
+            return Err(b"Loan not pending".to_vec());
+        }
+        let expires_at = loan
+            .start_timestamp
+            .get()
+            .saturating_add(self.pending_expiry_seconds.get());
+        if U64::from(self.vm().block_timestamp()) < expires_at {
+            return Err(b"Loan not expired".to_vec());
+        }
+
+        self.loans.setter(loan_id).status.set(U8::from(6));
+        self._release_collateral(loan_id, nft_id)?;
+
+        log(self.vm(), LoanExpired { loan_id });
+        Ok(())
+    }
+
+    pub fn make_payment(&mut self, loan_id: U256, amount: U256) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+        if sender != loan.borrower.get() {
+            return Err(b"Only borrower can pay".to_vec());
+        }
+        self._process_payment(loan_id, sender, amount, 1)?;
+        Ok(())
+    }
+
+    // Closes the loan at the live payoff figure, pulled in the same call so
+    // there's no stale quote. Any interest-only months left are dropped.
+    pub fn payoff_loan(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+        if sender != loan.borrower.get() {
+            return Err(b"Only borrower can pay".to_vec());
+        }
+        self.loans.setter(loan_id).interest_only_remaining.set(U32::ZERO);
+        // capped to what's owed, so this takes exactly the payoff
+        self._process_payment(loan_id, sender, U256::MAX, 1)?;
+        Ok(())
+    }
+
+    pub fn process_auto_repayment(
+        &mut self,
+        loan_id: U256,
+        remittance_amount: U256,
+    ) -> Result<U256, Vec<u8>> {
+        if self.vm().msg_sender() != self.oracle.get() {
+            return Err(b"Only oracle".to_vec());
+        }
+        if !self.loans.getter(loan_id).auto_repay_enabled.get() {
+            return Err(b"Auto-repay not enabled".to_vec());
+        }
+
+        // Far above the loan's usual remittance: nothing is taken, and the
+        // amount is added to what's held for the admin to confirm or drop.
+        // Returning normally keeps the event, and the oracle sees it all come
+        // back as leftover.
+        let multiple = U256::from(self.anomaly_multiple.get());
+        let average = self.loans.getter(loan_id).avg_remittance.get();
+        if multiple > U256::ZERO
+            && average > U256::ZERO
+            && remittance_amount > average.saturating_mul(multiple)
+        {
+            let held = self.loans.getter(loan_id).held_remittance.get();
+            let held = held.saturating_add(remittance_amount);
+            self.loans.setter(loan_id).held_remittance.set(held);
+            log(self.vm(), AnomalousRemittanceBlocked { loan_id, amount: remittance_amount });
+            return Ok(remittance_amount);
+        }
+
+        self._apply_remittance(loan_id, remittance_amount)
+    }
+
+    // Settles a remittance held by the anomaly check: confirmed, it is applied
+    // as auto-repay would have; otherwise it is discarded. Returns what
+    // wasn't applied.
+    pub fn resolve_held_remittance(
+        &mut self,
+        loan_id: U256,
+        confirm: bool,
+    ) -> Result<U256, Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        let held = self.loans.getter(loan_id).held_remittance.get();
+        if held == U256::ZERO {
+            return Err(b"No held remittance".to_vec());
+        }
+        self.loans.setter(loan_id).held_remittance.set(U256::ZERO);
+        if !confirm {
+            return Ok(held);
+        }
+        self._apply_remittance(loan_id, held)
+    }
+
+    // (rolling average remittance, remittance held for review)
+    pub fn get_remittance_stats(&self, loan_id: U256) -> (U256, U256) {
+        let loan = self.loans.getter(loan_id);
+        (loan.avg_remittance.get(), loan.held_remittance.get())
+    }
+
+    // Auto-repay holds any remittance above this many times the loan's
+    // rolling average. 0 disables the check.
+    pub fn set_anomaly_multiple(&mut self, multiple: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.anomaly_multiple.set(U32::from(multiple));
+        Ok(())
+    }
+
+    pub fn get_anomaly_multiple(&self) -> U32 {
+        self.anomaly_multiple.get()
+    }
+
+    pub fn set_auto_repay(&mut self, loan_id: U256, enabled: bool) -> Result<(), Vec<u8>> {
+        if self.vm().msg_sender() != self.loans.getter(loan_id).borrower.get() {
+            return Err(b"Only borrower".to_vec());
+        }
+        self.loans.setter(loan_id).auto_repay_enabled.set(enabled);
+        Ok(())
+    }
+
+    pub fn is_auto_repay_enabled(&self, loan_id: U256) -> bool {
+        self.loans.getter(loan_id).auto_repay_enabled.get()
+    }
+
+    // ---- Mark payment missed ----
+    // `keeper` is the operator whose report the oracle is relaying; a
+    // default it triggers pays the keeper reward to them.
+    pub fn mark_payment_missed(&mut self, loan_id: U256, keeper: Address) -> Result<(), Vec<u8>> {
+        if self.vm().msg_sender() != self.oracle.get() {
+            return Err(b"Only oracle".to_vec());
+        }
+        if self.paused.get() {
+            return Err(b"Paused".to_vec());
+        }
+
+        if !self.is_payment_overdue(loan_id) {
+            return Err(b"Payment not overdue".to_vec());
+        }
+        // each due date is only missed once: once next_payment_due has been
+        // counted, the next miss is for the period after the last one marked
+        let now = U64::from(self.vm().block_timestamp());
+        let due = self.loans.getter(loan_id).next_payment_due.get();
+        let marked = self.missed_due.get(loan_id);
+        let period_due = if marked >= due {
+            marked.saturating_add(U64::from(PAYMENT_PERIOD))
+        } else {
+            due
+        };
+        if now <= period_due.saturating_add(self._payment_grace(loan_id)) {
+            return Err(b"Payment not overdue".to_vec());
+        }
+        self.missed_due.insert(loan_id, period_due);
+        self._compound_interest(loan_id);
+
+        let mut loan = self.loans.setter(loan_id);
+        let missed = loan.payments_missed.get().saturating_add(U32::from(1));
+        loan.payments_missed.set(missed);
+
+        if missed >= U32::from(2u64) && loan.status.get() != U8::from(3) {
+            loan.status.set(U8::from(3)); // Defaulted
+            loan.defaulted_at.set(now);
+            self._leave_aggregate(loan_id);
+            self._pay_keeper_reward(loan_id, keeper, 0)?;
+        }
+
+        let missed_count = missed.to::<u32>();
+        log(self.vm(), PaymentMissed { loan_id, missed_count });
+        Ok(())
+    }
+
+    pub fn set_liquidation_delay(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.liquidation_delay_seconds.set(U64::from(seconds));
+        Ok(())
+    }
+
+    pub fn get_liquidation_delay(&self) -> U64 {
+        self.liquidation_delay_seconds.get()
+    }
+
+    // 0 disables value-based liquidation
+    pub fn set_liquidation_ltv_threshold(&mut self, threshold_bps: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.liquidation_ltv_threshold_bps.set(U32::from(threshold_bps));
+        Ok(())
+    }
+
+    pub fn get_liquidation_ltv_threshold(&self) -> U32 {
+        self.liquidation_ltv_threshold_bps.get()
+    }
+
+    // Circuit breaker on approvals: collateral value behind all active loans
+    // must stay at or above this share of what they owe, new loan included.
+    // 0 disables it.
+    pub fn set_min_aggregate_collateral(&mut self, ratio_bps: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.min_aggregate_collateral_bps.set(U32::from(ratio_bps));
+        Ok(())
+    }
+
+    pub fn get_min_aggregate_collateral(&self) -> U32 {
+        self.min_aggregate_collateral_bps.get()
+    }
+
+    // Collateral value of active loans over their outstanding balances, in
+    // bps. U256::MAX while nothing is outstanding.
+    pub fn get_aggregate_collateral_ratio(&self) -> U256 {
+        let (collateral_value, outstanding) = self._aggregate_position();
+        Self::_collateral_ratio(collateral_value, outstanding)
+    }
+
+    // Re-values one active loan's collateral in the aggregate. Anyone can
+    // call it; the totals only move to what the NFT is worth now.
+    pub fn refresh_aggregate_collateral(&mut self, loan_id: U256) -> Result<U256, Vec<u8>> {
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+        let value = self.get_collateral_valuation(loan.nft_collateral_id.get())?;
+        let previous = self.collateral_snapshot.get(loan_id);
+        self.collateral_snapshot.insert(loan_id, value);
+        let collateral_value = self.active_collateral_value.get();
+        self.active_collateral_value.set(collateral_value - previous + value);
+        Ok(value)
+    }
+
+    // Keeper check for loans that are current on payments but whose
+    // collateral no longer covers them. Flags the loan (starting the
+    // liquidation delay) while above the threshold and clears the flag once
+    // back under it. Returns whether the loan is flagged.
+    pub fn check_and_flag_undercollateralized(&mut self, loan_id: U256) -> Result<bool, Vec<u8>> {
+        if self.loans.getter(loan_id).status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+        let ltv = self.get_effective_ltv(loan_id)?;
+        let under = self._above_liquidation_ltv(ltv);
+        let flagged = self.loans.getter(loan_id).undercollateralized_at.get() > U64::ZERO;
+
+        if under && !flagged {
+            let now = U64::from(self.vm().block_timestamp());
+            self.loans.setter(loan_id).undercollateralized_at.set(now);
+            log(self.vm(), LoanUndercollateralized { loan_id, ltv });
+            let keeper = self.vm().msg_sender();
+            self._pay_keeper_reward(loan_id, keeper, 2)?;
+        } else if !under && flagged {
+            self.loans.setter(loan_id).undercollateralized_at.set(U64::ZERO);
+        }
+        Ok(under)
+    }
+
+    // Once the delay after default has passed, anyone can close the loan as
+    // liquidated. The NFT stays staked to the loan as seized collateral. An
+    // active loan flagged as undercollateralized qualifies the same way if
+    // it's still over the threshold.
+    pub fn liquidate(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        self._check_liquidatable(loan_id)?;
+        let loan = self.loans.getter(loan_id);
+        let status = loan.status.get();
+        let flagged_at = loan.undercollateralized_at.get();
+        let outstanding = loan.outstanding_balance.get();
+
+        if status == U8::from(1) {
+            // write-off timing runs from here
+            self.loans.setter(loan_id).defaulted_at.set(flagged_at);
+            self._leave_aggregate(loan_id);
+        }
+
+        self.loans.setter(loan_id).status.set(U8::from(4));
+
+        let event = LoanLiquidated { loan_id, outstanding };
+        log(self.vm(), event);
+        let keeper = self.vm().msg_sender();
+        self._pay_keeper_reward(loan_id, keeper, 1)?;
+        Ok(())
+    }
+
+    // Paid to whoever triggers a default, liquidation or undercollateralized
+    // flag, out of keeper_fund, at most once per loan for each of the three.
+    // Once the fund runs low the reward shrinks to what's left rather than
+    // blocking the action.
+    pub fn set_keeper_reward(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.keeper_reward.set(amount);
+        Ok(())
+    }
+
+    // Anyone can top up the fund, e.g. the admin out of released reserve.
+    pub fn fund_keeper_rewards(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        if amount == U256::ZERO {
+            return Err(b"Amount must be > 0".to_vec());
+        }
+        let sender = self.vm().msg_sender();
+        let contract = self.vm().contract_address();
+        self._safe_transfer_from(sender, contract, amount)?;
+        self.keeper_fund.set(self.keeper_fund.get() + amount);
+        Ok(())
+    }
+
+    pub fn withdraw_keeper_fund(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        let fund = self.keeper_fund.get();
+        if amount > fund {
+            return Err(b"Insufficient keeper fund".to_vec());
+        }
+        self.keeper_fund.set(fund - amount);
+        let admin = self.admin.get();
+        self._safe_transfer(admin, amount)
+    }
+
+    // (reward per action, fund balance, rewards paid to date)
+    pub fn get_keeper_reward_info(&self) -> (U256, U256, U256) {
+        (self.keeper_reward.get(), self.keeper_fund.get(), self.total_keeper_rewards.get())
+    }
+
+    pub fn get_keeper_earnings(&self, keeper: Address) -> U256 {
+        self.keeper_earnings.get(keeper)
+    }
+
+    // longest term extend_term can take a loan to, 0 for no limit
+    pub fn set_max_duration_months(&mut self, months: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.max_duration_months.set(U32::from(months));
+        Ok(())
+    }
+
+    pub fn get_max_duration_months(&self) -> U32 {
+        self.max_duration_months.get()
+    }
+
+    // Hardship relief: stretches an active loan's term and re-spreads what's
+    // left over the months that remain, lowering the monthly payment.
+    pub fn extend_term(&mut self, loan_id: U256, additional_months: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if additional_months == 0 {
+            return Err(b"Invalid extension".to_vec());
+        }
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+        let duration = loan.duration_months.get().saturating_add(U32::from(additional_months));
+        let max_duration = self.max_duration_months.get();
+        if max_duration > U32::ZERO && duration > max_duration {
+            return Err(b"Term exceeds maximum".to_vec());
+        }
+
+        self.loans.setter(loan_id).duration_months.set(duration);
+        self._recompute_monthly_payment(loan_id);
+
+        let event = LoanTermExtended { loan_id, additional_months };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    pub fn set_write_off_period(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.write_off_period.set(U64::from(seconds));
+        Ok(())
+    }
+
+    pub fn get_write_off_period(&self) -> U64 {
+        self.write_off_period.get()
+    }
+
+    // Gives up on a loan that has sat in default past the write-off period:
+    // the outstanding balance is booked as a loss against the pool, the NFT
+    // stays staked as seized collateral and the loan leaves the active books.
+    pub fn write_off(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        let loan = self.loans.getter(loan_id);
+        let status = loan.status.get();
+        if status != U8::from(3) && status != U8::from(4) {
+            return Err(b"Loan not defaulted".to_vec());
+        }
+        let write_off_at = loan
+            .defaulted_at
+            .get()
+            .saturating_add(self.write_off_period.get());
+        if U64::from(self.vm().block_timestamp()) < write_off_at {
+            return Err(b"Write-off period not elapsed".to_vec());
+        }
+        // compounded interest was never lent out, so it isn't a pool loss
+        let loss = loan.outstanding_balance.get() - loan.capitalized_interest.get();
+        let nft_id = loan.nft_collateral_id.get();
+
+        {
+            let mut loan = self.loans.setter(loan_id);
+            loan.outstanding_balance.set(U256::ZERO);
+            loan.capitalized_interest.set(U256::ZERO);
+            loan.status.set(U8::from(5)); // WrittenOff
+        }
+
+        {
+            let pool = ILendingPool::new(self.lending_pool.get());
+            pool.write_off(&mut *self, loan_id, loss)?;
+        }
+
+        self._seize_collateral(loan_id, nft_id);
+
+        let event = LoanWrittenOff { loan_id, loss };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    // (collateral value set against repay_amount, whether the collateral
+    // covers it) for a loan liquidate would accept right now, defaulted or
+    // flagged undercollateralized. Liquidation pays no bonus, the keeper
+    // reward is the incentive. (0, false) when the loan can't be liquidated.
+    pub fn preview_liquidation(
+        &self,
+        loan_id: U256,
+        repay_amount: U256,
+    ) -> Result<(U256, bool), Vec<u8>> {
+        if self._check_liquidatable(loan_id).is_err() {
+            return Ok((U256::ZERO, false));
+        }
+        let loan = self.loans.getter(loan_id);
+        let repaid = repay_amount.min(loan.outstanding_balance.get());
+        let valuation = self.get_collateral_valuation(loan.nft_collateral_id.get())?;
+        Ok((repaid.min(valuation), valuation >= repaid))
+    }
+
+    // A defaulted loan that hasn't been liquidated yet is restored when the
+    // borrower pays every missed installment in one go. There are no late
+    // fees yet, so the arrears are just the missed installments, charged as
+    // _process_payment charges them: interest-only months at their interest.
+    // A loan defaulted with nothing missed (settle_loans) can't be cured.
+    pub fn cure_default(&mut self, loan_id: U256, amount: U256) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(3) {
+            return Err(b"Loan not defaulted".to_vec());
+        }
+        if sender != loan.borrower.get() {
+            return Err(b"Only borrower can pay".to_vec());
+        }
+        let missed = loan.payments_missed.get();
+        if missed == U32::ZERO {
+            return Err(b"No missed payments".to_vec());
+        }
+        if self.nft_to_loan.get(loan.nft_collateral_id.get()) != loan_id {
+            return Err(b"Collateral not held for loan".to_vec());
+        }
+        let installment = if loan.interest_only_remaining.get() > U32::ZERO {
+            Self::_calculate_interest_portion(
+                loan.outstanding_balance.get(),
+                loan.interest_rate_bps.get(),
+                self.round_interest_up.get(),
+            )
+        } else {
+            loan.monthly_payment.get()
+        };
+        let arrears = installment * U256::from(missed);
+        if amount < arrears {
+            return Err(b"Amount below arrears".to_vec());
+        }
+        let installments = missed.to::<u32>();
+        let due = loan.next_payment_due.get();
+
+        {
+            let mut loan = self.loans.setter(loan_id);
+            loan.payments_missed.set(U32::ZERO);
+            loan.defaulted_at.set(U64::ZERO);
+            loan.status.set(U8::from(1));
+        }
+        self._enter_aggregate(loan_id)?;
+        // the arrears settle every missed month, so the due date catches up
+        // by that many and only a payment beyond them counts as prepaid
+        self._process_payment(loan_id, sender, amount, installments)?;
+        // interest compounded on the way in can leave the arrears short
+        let loan = self.loans.getter(loan_id);
+        let caught_up = due.saturating_add(U64::from(PAYMENT_PERIOD * u64::from(installments)));
+        if loan.status.get() == U8::from(1) && loan.next_payment_due.get() < caught_up {
+            return Err(b"Amount below arrears".to_vec());
+        }
+
+        log(self.vm(), LoanCured { loan_id });
+        Ok(())
+    }
+
+    // Closes an active loan whose balance is already zero but never flipped to
+    // Repaid, and releases the collateral if still staked.
+    pub fn finalize_loan(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        let loan = self.loans.getter(loan_id);
+        let nft_id = loan.nft_collateral_id.get();
+        if sender != loan.borrower.get() && sender != self.admin.get() {
+            return Err(b"Only borrower or admin".to_vec());
+        }
+        if loan.status.get() != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+        if loan.outstanding_balance.get() != U256::ZERO {
+            return Err(b"Balance outstanding".to_vec());
+        }
+
+        self.loans.setter(loan_id).status.set(U8::from(2));
+        self._leave_aggregate(loan_id);
+        self._release_collateral(loan_id, nft_id)?;
+
+        let event = LoanStatusChanged { loan_id, status: 2 };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    // Retries returning the collateral of a repaid loan to the borrower.
+    pub fn rescue_collateral(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        let loan = self.loans.getter(loan_id);
+        let borrower = loan.borrower.get();
+        let nft_id = loan.nft_collateral_id.get();
+        if sender != borrower && sender != self.admin.get() {
+            return Err(b"Only borrower or admin".to_vec());
+        }
+        if loan.status.get() != U8::from(2) {
+            return Err(b"Loan not closed".to_vec());
+        }
+        if self.nft_to_loan.get(nft_id) != loan_id {
+            return Err(b"Collateral not held for loan".to_vec());
+        }
+
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (_, _, _, _, staked) = nft.get_remittance(&mut *self, nft_id)?;
+        if !staked && self.nft_depositors.get(nft_id) == Address::ZERO {
+            return Err(b"Collateral not staked".to_vec());
+        }
+        self._release_collateral(loan_id, nft_id)?;
+        Ok(())
+    }
+
+    // Migration tool: force active loans into a terminal status (2=Repaid,
+    // 3=Defaulted). Only while paused. Repaid loans release their collateral;
+    // defaulted ones keep it staked for a liquidation or write-off, as
+    // liquidate does. Pending loans were never funded, so they're cancelled
+    // or left to expire instead.
+    pub fn settle_loans(&mut self, loan_ids: Vec<U256>, statuses: Vec<U8>) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if !self.paused.get() {
+            return Err(b"Not paused".to_vec());
+        }
+        if loan_ids.len() != statuses.len() {
+            return Err(b"Length mismatch".to_vec());
+        }
+
+        for (loan_id, status) in loan_ids.into_iter().zip(statuses) {
+            if status != U8::from(2) && status != U8::from(3) {
+                return Err(b"Invalid status".to_vec());
+            }
+            let loan = self.loans.getter(loan_id);
+            let current = loan.status.get();
+            let nft_id = loan.nft_collateral_id.get();
+            if loan.borrower.get() == Address::ZERO {
+                return Err(b"Loan not found".to_vec());
+            }
+            if current != U8::from(1) {
+                return Err(b"Loan not active".to_vec());
+            }
+
+            let now = U64::from(self.vm().block_timestamp());
+            let mut loan = self.loans.setter(loan_id);
+            loan.status.set(status);
+            if status == U8::from(3) {
+                loan.defaulted_at.set(now);
+            }
+            self._leave_aggregate(loan_id);
+
+            if status == U8::from(2) {
+                self._release_collateral(loan_id, nft_id)?;
+            }
+
+            let event = LoanStatusChanged { loan_id, status: status.to::<u8>() };
+            log(self.vm(), event);
+        }
+        Ok(())
+    }
+
+    // one year of the NFT's monthly remittance, weighted by reliability
+    pub fn get_collateral_valuation(&self, nft_id: U256) -> Result<U256, Vec<u8>> {
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (_, monthly_amount, reliability_score, _, _) = nft.get_remittance(self, nft_id)?;
+        Ok(Self::_collateral_valuation(monthly_amount, reliability_score))
+    }
+
+    // Outstanding balance against what the collateral is worth today, in bps.
+    // The score is decayed for stale remittances, so an NFT that stops being
+    // fed drives this up. Worthless collateral under a balance reads U256::MAX.
+    pub fn get_effective_ltv(&self, loan_id: U256) -> Result<U256, Vec<u8>> {
+        let loan = self.loans.getter(loan_id);
+        let outstanding = loan.outstanding_balance.get();
+        let nft_id = loan.nft_collateral_id.get();
+        if outstanding == U256::ZERO {
+            return Ok(U256::ZERO);
+        }
+
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (_, monthly_amount, reliability_score, _, _) = nft.get_remittance(self, nft_id)?;
+        let reliability_score = self._effective_reliability(nft_id, reliability_score)?;
+        let valuation = Self::_collateral_valuation(monthly_amount, reliability_score);
+        if valuation == U256::ZERO {
+            return Ok(U256::MAX);
+        }
+        Ok(outstanding * U256::from(BPS_DENOMINATOR) / valuation)
+    }
+
+    pub fn get_available_to_borrow(&self, nft_id: U256) -> Result<U256, Vec<u8>> {
+        let ltv_limit = self._max_borrow(self.get_collateral_valuation(nft_id)?);
+        let pool_available =
+            ILendingPool::new(self.lending_pool.get()).get_available_liquidity(self)?;
+        Ok(ltv_limit.min(pool_available))
+    }
+
+    // (pool liquidity, value of collateral behind active loans)
+    pub fn get_tvl(&self) -> Result<(U256, U256), Vec<u8>> {
+        let pool_liquidity = ILendingPool::new(self.lending_pool.get()).get_total_liquidity(self)?;
+        let (collateral_value, _) = self._aggregate_position();
+        Ok((pool_liquidity, collateral_value))
+    }
+
+    // What the lender would lose if this loan's outstanding balance became bad
+    // debt, split by their share of pool liquidity. Zero for closed loans.
+    pub fn simulate_default_impact(&self, loan_id: U256, lender: Address) -> Result<U256, Vec<u8>> {
+        let loan = self.loans.getter(loan_id);
+        let status = loan.status.get();
+        if status != U8::from(1) && status != U8::from(3) {
+            return Ok(U256::ZERO);
+        }
+        self._lender_share(lender, loan.outstanding_balance.get())
+    }
+
+    // The part of the loan's outstanding principal the lender's deposit
+    // funded, pro rata to their share of pool liquidity.
+    pub fn get_lender_exposure(&self, lender: Address, loan_id: U256) -> U256 {
+        let loan = self.loans.getter(loan_id);
+        let status = loan.status.get();
+        if status != U8::from(1) && status != U8::from(3) {
+            return U256::ZERO;
+        }
+        let principal = loan.outstanding_balance.get() - loan.capitalized_interest.get();
+        self._lender_share(lender, principal).unwrap_or(U256::ZERO)
+    }
+
+    // (interest-only months at origination, interest-only months left)
+    pub fn get_interest_only_months(&self, loan_id: U256) -> (U32, U32) {
+        let loan = self.loans.getter(loan_id);
+        (loan.interest_only_months.get(), loan.interest_only_remaining.get())
+    }
+
+    pub fn get_remaining_term(&self, loan_id: U256) -> U32 {
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return U32::ZERO;
+        }
+        loan.duration_months.get().saturating_sub(loan.payments_made.get())
+    }
+
+    // keccak256(abi.encodePacked(loan_id, borrower, loan_amount,
+    // interest_rate_bps, duration_months, monthly_payment))
+    pub fn get_loan_terms_hash(&self, loan_id: U256) -> B256 {
+        let loan = self.loans.getter(loan_id);
+        let mut packed = Vec::with_capacity(124);
+        packed.extend_from_slice(&loan_id.to_be_bytes::<32>());
+        packed.extend_from_slice(loan.borrower.get().as_slice());
+        packed.extend_from_slice(&loan.loan_amount.get().to_be_bytes::<32>());
+        packed.extend_from_slice(&loan.interest_rate_bps.get().to_be_bytes::<4>());
+        packed.extend_from_slice(&loan.duration_months.get().to_be_bytes::<4>());
+        packed.extend_from_slice(&loan.monthly_payment.get().to_be_bytes::<32>());
+        keccak(packed)
+    }
+
+    pub fn get_external_ref(&self, loan_id: U256) -> B256 {
+        self.loans.getter(loan_id).external_ref.get()
+    }
+
+    // Links the loan to its off-chain agreement (IPFS/HTTPS). The borrower
+    // can set it while the loan is pending, the admin at any time.
+    pub fn set_agreement_uri(&mut self, loan_id: U256, uri: String) -> Result<(), Vec<u8>> {
+        let sender = self.vm().msg_sender();
+        let loan = self.loans.getter(loan_id);
+        let borrower_pending = sender == loan.borrower.get() && loan.status.get() == U8::from(0);
+        if sender != self.admin.get() && !borrower_pending {
+            return Err(b"Not authorized".to_vec());
+        }
+        self.loans.setter(loan_id).agreement_uri.set_str(uri);
+        Ok(())
+    }
+
+    pub fn get_agreement_uri(&self, loan_id: U256) -> String {
+        self.loans.getter(loan_id).agreement_uri.get_string()
+    }
+
+    pub fn get_outstanding_balance(&self, loan_id: U256) -> U256 {
+        self.loans.getter(loan_id).outstanding_balance.get()
+    }
+
+    // (loan_id, borrower, nft_collateral_id, loan_amount, outstanding_balance,
+    //  total_repaid, interest_rate_bps, duration_months, monthly_payment,
+    //  start_timestamp, next_payment_due, status, payments_made, payments_missed)
+    // All zero for an unknown id. Later fields have their own getters.
+    #[allow(clippy::type_complexity)]
+    pub fn get_loan(
+        &self,
+        loan_id: U256,
+    ) -> (U256, Address, U256, U256, U256, U256, U32, U32, U256, U64, U64, U8, U32, U32) {
+        let loan = self.loans.getter(loan_id);
+        (
+            loan.loan_id.get(),
+            loan.borrower.get(),
+            loan.nft_collateral_id.get(),
+            loan.loan_amount.get(),
+            loan.outstanding_balance.get(),
+            loan.total_repaid.get(),
+            loan.interest_rate_bps.get(),
+            loan.duration_months.get(),
+            loan.monthly_payment.get(),
+            loan.start_timestamp.get(),
+            loan.next_payment_due.get(),
+            loan.status.get(),
+            loan.payments_made.get(),
+            loan.payments_missed.get(),
+        )
+    }
+
+    // What the borrower actually received. The full loan_amount is still owed.
+    pub fn get_disbursed_amount(&self, loan_id: U256) -> U256 {
+        self.loans.getter(loan_id).disbursed_amount.get()
+    }
+
+    pub fn get_loan_status(&self, loan_id: U256) -> U8 {
+        self.loans.getter(loan_id).status.get()
+    }
+
+    // (0=free, 1=collateralizing an active loan, 2=defaulted/seized, loan_id)
+    pub fn get_collateral_status(&self, nft_id: U256) -> (U8, U256) {
+        let loan_id = self.nft_to_loan.get(nft_id);
+        if loan_id == U256::ZERO {
+            return (U8::ZERO, U256::ZERO);
+        }
+        match self.loans.getter(loan_id).status.get().to::<u8>() {
+            1 => (U8::from(1), loan_id),
+            3 | 4 => (U8::from(2), loan_id),
+            _ => (U8::ZERO, U256::ZERO),
+        }
+    }
+
+    // when the current schedule pays the loan off, 0 once it's closed
+    pub fn get_expected_completion(&self, loan_id: U256) -> U64 {
+        let loan = self.loans.getter(loan_id);
+        if loan.status.get() != U8::from(1) {
+            return U64::ZERO;
+        }
+        let term = u64::from(loan.duration_months.get().to::<u32>()) * PAYMENT_PERIOD;
+        loan.start_timestamp.get().saturating_add(U64::from(term))
+    }
+
+    pub fn get_loan_age(&self, loan_id: U256) -> U64 {
+        let start = self.loans.getter(loan_id).start_timestamp.get();
+        U64::from(self.vm().block_timestamp()).saturating_sub(start)
+    }
+
+    // every loan id the borrower has requested, oldest first
+    pub fn get_borrower_loans(&self, borrower: Address) -> Vec<U256> {
+        let loans = self.borrower_loans.getter(borrower);
+        let mut ids = Vec::with_capacity(loans.len());
+        for i in 0..loans.len() {
+            if let Some(loan_id) = loans.get(i) {
+                ids.push(loan_id);
+            }
+        }
+        ids
+    }
+
+    // 0 = none, 1 = at least one loan repaid (half origination fee),
+    // 2 = three or more repaid (fee waived). Any loan that is open or went
+    // bad drops the borrower to 0; pending and cancelled requests don't count.
+    pub fn get_loyalty_tier(&self, borrower: Address) -> U8 {
+        let loans = self.borrower_loans.getter(borrower);
+        let mut repaid = 0u32;
+        for i in 0..loans.len() {
+            let Some(loan_id) = loans.get(i) else { continue };
+            match self.loans.getter(loan_id).status.get().to::<u8>() {
+                0 | 6 => {}
+                2 => repaid += 1,
+                _ => return U8::ZERO,
+            }
+        }
+        match repaid {
+            0 => U8::ZERO,
+            1 | 2 => U8::from(1),
+            _ => U8::from(2),
+        }
+    }
+
+    pub fn get_loan_collateral(&self, loan_id: U256) -> U256 {
+        self.loans.getter(loan_id).nft_collateral_id.get()
+    }
+
+    // (timestamp, amount, principal, interest) for every payment, oldest first
+    pub fn get_payment_history(&self, loan_id: U256) -> Vec<(U64, U256, U256, U256)> {
+        let records = self.loan_payments.getter(loan_id);
+        let mut history = Vec::with_capacity(records.len());
+        for i in 0..records.len() {
+            let Some(record) = records.getter(i) else { continue };
+            history.push((
+                record.timestamp.get(),
+                record.amount.get(),
+                record.principal.get(),
+                record.interest.get(),
+            ));
+        }
+        history
+    }
+
+    // (duration_history_multiple, reliability_decay_per_month, max_ltv_bps,
+    //  safety_buffer_bps, payment_waterfall, grace_period,
+    //  liquidation_delay_seconds, paused, round_interest_up, origination_fee_bps,
+    //  pending_expiry_seconds, min_rate_bps, max_rate_bps,
+    //  write_off_period, compounding_period, max_duration_months,
+    //  liquidation_ltv_threshold_bps, first_payment_grace,
+    //  min_aggregate_collateral_bps, anomaly_multiple, keeper_reward)
+    #[allow(clippy::type_complexity)]
+    pub fn get_parameters(
+        &self,
+    ) -> (
+        U32, U32, U32, U32, U8, U64, U64, bool, bool, U32, U64, U32, U32, U64, U64, U32, U32, U64,
+        U32, U32, U256,
+    ) {
+        (
+            self.duration_history_multiple.get(),
+            self.reliability_decay_per_month.get(),
+            self.max_ltv_bps.get(),
+            self.safety_buffer_bps.get(),
+            self.payment_waterfall.get(),
+            self.grace_period.get(),
+            self.liquidation_delay_seconds.get(),
+            self.paused.get(),
+            self.round_interest_up.get(),
+            self.origination_fee_bps.get(),
+            self.pending_expiry_seconds.get(),
+            self.min_rate_bps.get(),
+            self.max_rate_bps.get(),
+            self.write_off_period.get(),
+            self.compounding_period.get(),
+            self.max_duration_months.get(),
+            self.liquidation_ltv_threshold_bps.get(),
+            self.first_payment_grace.get(),
+            self.min_aggregate_collateral_bps.get(),
+            self.anomaly_multiple.get(),
+            self.keeper_reward.get(),
+        )
+    }
+
+    // The configure_* calls set related tunables in one go. Each value goes
+    // through its own setter, so any invalid one reverts the whole group.
+    // They're grouped rather than one call taking everything because a
+    // tuple that wide is more than export-abi can lay out.
+    pub fn configure_risk(
+        &mut self,
+        duration_history_multiple: u32,
+        reliability_decay_per_month: u32,
+        max_ltv_bps: u32,
+        safety_buffer_bps: u32,
+        min_aggregate_collateral_bps: u32,
+        anomaly_multiple: u32,
+    ) -> Result<(), Vec<u8>> {
+        self.set_duration_history_multiple(duration_history_multiple)?;
+        self.set_reliability_decay_per_month(reliability_decay_per_month)?;
+        // each of the pair is checked against the other's current value, so
+        // apply them in whichever order keeps the two consistent
+        if U32::from(max_ltv_bps) > self.safety_buffer_bps.get() {
+            self.set_max_ltv_bps(max_ltv_bps)?;
+            self.set_safety_buffer_bps(safety_buffer_bps)?;
+        } else {
+            self.set_safety_buffer_bps(safety_buffer_bps)?;
+            self.set_max_ltv_bps(max_ltv_bps)?;
+        }
+        self.set_min_aggregate_collateral(min_aggregate_collateral_bps)?;
+        self.set_anomaly_multiple(anomaly_multiple)?;
+        Ok(())
+    }
+
+    pub fn configure_terms(
+        &mut self,
+        payment_waterfall: u8,
+        round_interest_up: bool,
+        origination_fee_bps: u32,
+        min_rate_bps: u32,
+        max_rate_bps: u32,
+        compounding_period: u64,
+    ) -> Result<(), Vec<u8>> {
+        self.set_payment_waterfall(payment_waterfall)?;
+        self.set_round_interest_up(round_interest_up)?;
+        self.set_origination_fee_bps(origination_fee_bps)?;
+        self.set_rate_bounds(min_rate_bps, max_rate_bps)?;
+        self.set_compounding_period(compounding_period)?;
+        Ok(())
+    }
+
+    pub fn configure_schedule(
+        &mut self,
+        grace_period: u64,
+        first_payment_grace: u64,
+        pending_expiry_seconds: u64,
+        max_duration_months: u32,
+    ) -> Result<(), Vec<u8>> {
+        self.set_grace_period(grace_period)?;
+        self.set_first_payment_grace(first_payment_grace)?;
+        self.set_pending_expiry(pending_expiry_seconds)?;
+        self.set_max_duration_months(max_duration_months)?;
+        Ok(())
+    }
+
+    pub fn configure_liquidation(
+        &mut self,
+        liquidation_delay_seconds: u64,
+        liquidation_ltv_threshold_bps: u32,
+        write_off_period: u64,
+        keeper_reward: U256,
+    ) -> Result<(), Vec<u8>> {
+        self.set_liquidation_delay(liquidation_delay_seconds)?;
+        self.set_liquidation_ltv_threshold(liquidation_ltv_threshold_bps)?;
+        self.set_write_off_period(write_off_period)?;
+        self.set_keeper_reward(keeper_reward)?;
+        Ok(())
+    }
+
+    // loan_id of zero takes the next counter id
+    #[allow(clippy::too_many_arguments)]
+    fn _create_loan(
+        &mut self,
+        loan_id: U256,
+        borrower: Address,
+        nft_id: U256,
+        amount: U256,
+        duration_months: u32,
+        interest_only_months: u32,
+        external_ref: B256,
+    ) -> Result<U256, Vec<u8>> {
+        if self.paused.get() {
+            return Err(b"Paused".to_vec());
+        }
+        if interest_only_months > 0 && interest_only_months >= duration_months {
+            return Err(b"Invalid interest-only term".to_vec());
+        }
+
+        // let (owner, _, reliability_score, _, _) = IRemittanceNFT::new(self.remittance_nft.get())
+        // .getRemittance(nft_id);
+        let remittance_nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (owner, monthly_amount, reliability_score, total_sent, staked) =
+            remittance_nft.get_remittance(&mut *self, nft_id)?;
+
+        // an NFT sent in through onERC721Received is owned by this contract
+        let deposited = owner == self.vm().contract_address()
+            && self.nft_depositors.get(nft_id) == borrower;
+        if owner != borrower && !deposited {
+            return Err(b"NFT does not belong to borrower".to_vec());
+        }
+        // one loan per NFT, pending ones included
+        if staked || self.nft_to_loan.get(nft_id) != U256::ZERO {
+            return Err(b"NFT already collateral".to_vec());
+        }
+
+        let valuation = Self::_collateral_valuation(monthly_amount, reliability_score);
+        if amount > self._max_borrow(valuation) {
+            return Err(b"Amount exceeds LTV limit".to_vec());
+        }
+        if !self._within_aggregate_limit(borrower, nft_id, amount, valuation)? {
+            return Err(b"Aggregate borrow limit exceeded".to_vec());
+        }
+
+        // term can't outrun the remittance history backing the NFT
+        let history_months = Self::_history_months(monthly_amount, total_sent);
+        let max_duration = history_months
+            .saturating_mul(U256::from(self.duration_history_multiple.get()));
+        if U256::from(duration_months) > max_duration {
+            return Err(b"Duration exceeds credit history".to_vec());
+        }
+
+        // While interest-only, the monthly payment is just the month's interest.
+        // Once those months are paid it is recomputed to amortize the full
+        // principal over the remaining duration_months - interest_only_months.
+        let reliability_score = self._effective_reliability(nft_id, reliability_score)?;
+        let interest_rate_bps = self._clamp_rate(Self::_calculate_interest_rate(reliability_score));
+        let monthly_payment = if interest_only_months > 0 {
+            Self::_calculate_interest_portion(
+                amount,
+                U32::from(interest_rate_bps),
+                self.round_interest_up.get(),
+            )
+        } else {
+            Self::_calculate_monthly_payment(amount, interest_rate_bps, duration_months)
+        };
+        // a payment that can't cover the first month's interest never pays
+        // the balance down
+        let first_interest = Self::_calculate_interest_portion(
+            amount,
+            U32::from(interest_rate_bps),
+            self.round_interest_up.get(),
+        );
+        if monthly_payment < first_interest {
+            return Err(b"Payment below interest accrual".to_vec());
+        }
+        let current_time = U64::from(self.vm().block_timestamp());
+        let next_pay_date = U64::from(
+            self.vm()
+                .block_timestamp()
+                .saturating_add(PAYMENT_PERIOD),
+        );
+
+        let loan_id = if loan_id == U256::ZERO {
+            let next = self.loan_counter.get() + U256::from(1u64);
+            self.loan_counter.set(next);
+            next
+        } else {
+            loan_id
+        };
+
+        let mut loan = self.loans.setter(loan_id);
+        loan.loan_id.set(loan_id);
+        loan.borrower.set(borrower);
+        loan.nft_collateral_id.set(nft_id);
+        loan.loan_amount.set(amount);
+        loan.outstanding_balance.set(amount);
+        loan.total_repaid.set(U256::ZERO);
+        loan.interest_rate_bps.set(U32::from(interest_rate_bps));
+        loan.duration_months.set(U32::from(duration_months));
+        loan.monthly_payment.set(monthly_payment);
+        loan.start_timestamp.set(current_time);
+        loan.next_payment_due.set(next_pay_date);
+        loan.status.set(U8::from(0));
+        loan.payments_made.set(U32::from(0));
+        loan.payments_missed.set(U32::from(0));
+        loan.interest_only_months.set(U32::from(interest_only_months));
+        loan.interest_only_remaining.set(U32::from(interest_only_months));
+        // on unless the borrower opted out when requesting
+        loan.auto_repay_enabled.set(true);
+        // the NFT's history seeds the anomaly check, so the first report is
+        // measured against something too
+        loan.avg_remittance.set(monthly_amount);
+        loan.external_ref.set(external_ref);
+
+        // self.loans.insert(loan_id, loan);
+
+        self.borrower_loans.setter(borrower).push(loan_id);
+        self.all_loan_ids.push(loan_id);
+        self.nft_to_loan.insert(nft_id, loan_id);
+
+        let req_loan = LoanRequested { borrower, loan_id, external_ref };
+        log(self.vm(), req_loan);
+        Ok(loan_id)
+    }
+
+    // `installments` is how many scheduled payments this one is meant to
+    // cover: 1 for a regular payment, the missed months for a cure.
+    fn _process_payment(
+        &mut self,
+        loan_id: U256,
+        payer: Address,
+        mut amount: U256,
+        installments: u32,
+    ) -> Result<U256, Vec<u8>> {
+        if amount == U256::ZERO {
+            return Err(b"Amount must be > 0".to_vec());
+        }
+        if self.paused.get() {
+            return Err(b"Paused".to_vec());
+        }
+        self._compound_interest(loan_id);
+
+        let lending_pool = self.lending_pool.get();
+        let loan = self.loans.getter(loan_id);
+        let outstanding = loan.outstanding_balance.get();
+        let interest_rate_bps = loan.interest_rate_bps.get();
+        let nft_id = loan.nft_collateral_id.get();
+        let interest_portion = Self::_calculate_interest_portion(
+            outstanding,
+            interest_rate_bps,
+            self.round_interest_up.get(),
+        );
+        let total_repaid = loan.total_repaid.get();
+        let payments_made = loan.payments_made.get();
+        let next_payment_due = loan.next_payment_due.get();
+        // let payments_missed = loan.payments_missed.get();
+        let status = loan.status.get();
+        let io_remaining = loan.interest_only_remaining.get();
+        let monthly_payment = loan.monthly_payment.get();
+
+        if status != U8::from(1) {
+            return Err(b"Loan not active".to_vec());
+        }
+
+        // interest-only months only ever take the interest, one month's per
+        // installment covered
+        let interest_only = io_remaining > U32::ZERO;
+        let interest_due = interest_portion * U256::from(installments);
+        if interest_only && amount > interest_due {
+            if interest_portion == U256::ZERO {
+                return Err(b"Amount must be > 0".to_vec());
+            }
+            amount = interest_due;
+        }
+
+        // a payoff only takes what is owed, the rest stays with the payer
+        let owed = interest_portion + outstanding;
+        let closes = amount >= owed;
+        if closes {
+            amount = owed;
+        }
+
+        // interest-only months are always applied interest first
+        let principal_first = !interest_only && self.payment_waterfall.get() == U8::from(1);
+        let (principal_portion, interest_paid) = if principal_first {
+            let principal = amount.min(outstanding);
+            (principal, (amount - principal).min(interest_portion))
+        } else {
+            // a payoff owes this month's interest, covering several
+            // installments owes that many months of it
+            let interest_cap = if closes { interest_portion } else { interest_due };
+            let interest = amount.min(interest_cap);
+            (amount - interest, interest)
+        };
+
+        // ERC20 Transfer
+        self._safe_transfer_from(payer, lending_pool, amount)?;
+
+        // compounded interest sits in the balance but was never lent out, so
+        // the pool books paying it down as interest rather than principal
+        let capitalized = self.loans.getter(loan_id).capitalized_interest.get();
+        let capitalized_paid = principal_portion.min(capitalized);
+        if closes {
+            self._leave_aggregate(loan_id);
+        } else {
+            let active = self.active_outstanding.get();
+            self.active_outstanding.set(active.saturating_sub(principal_portion));
+        }
+        {
+            let pool = ILendingPool::new(lending_pool);
+            pool.repay(
+                &mut *self,
+                principal_portion - capitalized_paid,
+                interest_paid + capitalized_paid,
+                loan_id,
+            )?;
+        }
+
+        {
+            let mut loan = self.loans.setter(loan_id);
+            loan.total_repaid.set(total_repaid + amount);
+            loan.capitalized_interest.set(capitalized - capitalized_paid);
+
+            let paid_before = if loan.current_period_due.get() == next_payment_due {
+                loan.current_period_paid.get()
+            } else {
+                U256::ZERO
+            };
+            // once this period's installments are covered they count as made,
+            // the due date rolls one PAYMENT_PERIOD forward per installment and
+            // the next period starts from nothing; a payoff covers them too
+            let installment = if interest_only { interest_portion } else { monthly_payment };
+            let period_paid = paid_before + amount;
+            let covered = period_paid >= installment * U256::from(installments);
+            if closes || covered {
+                loan.payments_made.set(payments_made + U32::from(installments));
+            }
+            if !closes && covered {
+                let next_due = next_payment_due
+                    .saturating_add(U64::from(PAYMENT_PERIOD * u64::from(installments)));
+                loan.next_payment_due.set(next_due);
+                loan.current_period_due.set(next_due);
+                loan.current_period_paid.set(U256::ZERO);
+            } else {
+                loan.current_period_due.set(next_payment_due);
+                loan.current_period_paid.set(period_paid);
+            }
+
+            if interest_only && amount == interest_due {
+                loan.interest_only_remaining
+                    .set(io_remaining.saturating_sub(U32::from(installments)));
+            }
+
+            if closes {
+                loan.outstanding_balance.set(U256::ZERO);
+                loan.status.set(U8::from(2)); // 2 = Fully repaid or closed
+            } else {
+                loan.outstanding_balance
+                    .set(outstanding - principal_portion);
+            }
+        }
+
+        // the payment amortizes from here once interest-only ends, and a
+        // prepayment beyond the scheduled amount re-spreads what's left
+        let io_ended =
+            interest_only && io_remaining <= U32::from(installments) && amount == interest_due;
+        let prepaid = !interest_only && amount > monthly_payment * U256::from(installments);
+        if !closes && (io_ended || prepaid) {
+            self._recompute_monthly_payment(loan_id);
+        }
+
+        {
+            let now = U64::from(self.vm().block_timestamp());
+            let mut records = self.loan_payments.setter(loan_id);
+            let mut record = records.grow();
+            record.timestamp.set(now);
+            record.amount.set(amount);
+            record.principal.set(principal_portion);
+            record.interest.set(interest_paid);
+        }
+
+        // Emit event
+        let event = PaymentMade { loan_id, amount };
+        log(self.vm(), event);
+
+        // Last, so a stuck NFT can't block a payoff: the loan is closed either
+        // way and rescue_collateral can retry the release.
+        if closes {
+            let _ = self._release_collateral(loan_id, nft_id);
+        }
+
+        Ok(amount)
+    }
+
+    fn _deterministic_loan_id(borrower: Address, nft_id: U256, salt: B256) -> U256 {
+        let mut packed = Vec::with_capacity(84);
+        packed.extend_from_slice(borrower.as_slice());
+        packed.extend_from_slice(&nft_id.to_be_bytes::<32>());
+        packed.extend_from_slice(salt.as_slice());
+        U256::from_be_bytes(keccak(packed).0)
+    }
+
+    // months of remittances the NFT has on record
+    fn _history_months(monthly_amount: U256, total_sent: U256) -> U256 {
+        if monthly_amount == U256::ZERO {
+            U256::ZERO
+        } else {
+            total_sent / monthly_amount
+        }
+    }
+
+    fn _collateral_valuation(monthly_amount: U256, reliability_score: U256) -> U256 {
+        let score = reliability_score.min(U256::from(100u64));
+        monthly_amount * U256::from(MONTHS_PER_YEAR) * score / U256::from(100u64)
+    }
+
+    fn _calculate_interest_rate(score: U256) -> u32 {
+        let s = (score % U256::from(100u64)).to::<u64>();
+        if s >= 90 {
+            1500
+        } else if s >= 80 {
+            2000
+        } else if s >= 70 {
+            3000
+        } else {
+            4000
+        }
+    }
+
+    fn _calculate_monthly_payment(principal: U256, rate_bps: u32, months: u32) -> U256 {
+        let total_interest =
+            principal * U256::from(rate_bps) * U256::from(months)
+                / U256::from(MONTHS_PER_YEAR * BPS_DENOMINATOR);
+        let total = principal + total_interest;
+        if months == 0 {
             total
         } else {
             total / U256::from(months)
         }
     }
 
-    fn _calculate_interest_portion(outstanding: U256, annual_rate_bps: U32) -> U256 {
-        let monthly_rate = annual_rate_bps / U32::from(12u64);
-        (outstanding * U256::from(monthly_rate)) / U256::from(10000u64)
+    fn _calculate_interest_portion(
+        outstanding: U256,
+        annual_rate_bps: U32,
+        round_up: bool,
+    ) -> U256 {
+        let monthly_rate = annual_rate_bps / U32::from(MONTHS_PER_YEAR);
+        let numerator = outstanding * U256::from(monthly_rate);
+        if round_up {
+            Self::_div_ceil(numerator, U256::from(BPS_DENOMINATOR))
+        } else {
+            numerator / U256::from(BPS_DENOMINATOR)
+        }
+    }
+
+    fn _div_ceil(numerator: U256, denominator: U256) -> U256 {
+        let quotient = numerator / denominator;
+        if numerator % denominator == U256::ZERO {
+            quotient
+        } else {
+            quotient + U256::from(1u64)
+        }
+    }
+}
+
+impl LoanManager {
+    // Takes up to one monthly payment out of the remittance and folds it into
+    // the loan's rolling average, weighting the newest a quarter. Returns the
+    // leftover.
+    fn _apply_remittance(&mut self, loan_id: U256, amount: U256) -> Result<U256, Vec<u8>> {
+        let loan = self.loans.getter(loan_id);
+        let borrower = loan.borrower.get();
+        let payment_amount = amount.min(loan.monthly_payment.get());
+        let average = loan.avg_remittance.get();
+        let average = if average == U256::ZERO {
+            amount
+        } else {
+            (average * U256::from(3) + amount) / U256::from(4)
+        };
+
+        let applied = self._process_payment(loan_id, borrower, payment_amount, 1)?;
+        self.loans.setter(loan_id).avg_remittance.set(average);
+        Ok(amount - applied)
+    }
+
+    // A loan pays each action once, so flagging and clearing the same loan
+    // over and over earns nothing past the first flag.
+    fn _pay_keeper_reward(&mut self, loan_id: U256, keeper: Address, action: u8) -> Result<(), Vec<u8>> {
+        let bit = U8::from(1u8 << action);
+        let rewarded = self.keeper_rewarded.get(loan_id);
+        if rewarded & bit != U8::ZERO {
+            return Ok(());
+        }
+        let fund = self.keeper_fund.get();
+        let amount = self.keeper_reward.get().min(fund);
+        if amount == U256::ZERO {
+            return Ok(());
+        }
+        self.keeper_rewarded.insert(loan_id, rewarded | bit);
+        self.keeper_fund.set(fund - amount);
+        self.total_keeper_rewards.set(self.total_keeper_rewards.get() + amount);
+        let earned = self.keeper_earnings.get(keeper);
+        self.keeper_earnings.setter(keeper).set(earned + amount);
+        self._safe_transfer(keeper, amount)?;
+        log(self.vm(), KeeperRewarded { keeper, action, amount });
+        Ok(())
+    }
+
+    fn _safe_transfer(&mut self, to: Address, value: U256) -> Result<(), Vec<u8>> {
+        self._call_token(&transferCall { to, value }.abi_encode())
+    }
+
+    fn _safe_transfer_from(
+        &mut self,
+        from: Address,
+        to: Address,
+        value: U256,
+    ) -> Result<(), Vec<u8>> {
+        self._call_token(&transferFromCall { from, to, value }.abi_encode())
+    }
+
+    // tokens that return nothing are taken at their word, the rest must return true
+    fn _call_token(&mut self, calldata: &[u8]) -> Result<(), Vec<u8>> {
+        let token = self.usdc.get();
+        let returned = self.vm().call(&Call::new(), token, calldata)?;
+        if !returned.is_empty() && !bool::abi_decode(&returned, false).unwrap_or(false) {
+            return Err(b"Token transfer failed".to_vec());
+        }
+        Ok(())
+    }
+
+    // Unstakes and returns the NFT behind `loan_id`. Does nothing once the
+    // NFT is no longer mapped to this loan, so a stale loan can't free
+    // collateral that has since moved on to another one.
+    fn _release_collateral(&mut self, loan_id: U256, nft_id: U256) -> Result<(), Vec<u8>> {
+        if self.nft_to_loan.get(nft_id) != loan_id {
+            return Ok(());
+        }
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let (_, _, _, _, staked) = nft.get_remittance(&*self, nft_id)?;
+        if staked {
+            nft.unstake_nft(&mut *self, nft_id)?;
+        }
+
+        self.nft_to_loan.delete(nft_id);
+
+        let depositor = self.nft_depositors.get(nft_id);
+        if depositor != Address::ZERO {
+            let contract = self.vm().contract_address();
+            nft.transfer_from(&mut *self, contract, depositor, nft_id)?;
+            self.nft_depositors.delete(nft_id);
+        }
+        Ok(())
+    }
+
+    // Drops a written-off loan's hold on its NFT from the tracking mappings.
+    // The NFT itself stays staked, and a deposited one stays here, so it
+    // can't back another loan or be handed back.
+    fn _seize_collateral(&mut self, loan_id: U256, nft_id: U256) {
+        if self.nft_to_loan.get(nft_id) != loan_id {
+            return;
+        }
+        self.nft_to_loan.delete(nft_id);
+        self.nft_depositors.delete(nft_id);
+    }
+
+    // Capitalizes interest on an active loan for every whole compounding
+    // period since it fell behind (or was last compounded), returning what
+    // was added. Nothing compounds before the current due date.
+    fn _compound_interest(&mut self, loan_id: U256) -> U256 {
+        // bounds the loop, anything left over compounds on the next call
+        const MAX_PERIODS: u64 = 366;
+
+        let period = self.compounding_period.get().to::<u64>();
+        let loan = self.loans.getter(loan_id);
+        if period == 0 || loan.status.get() != U8::from(1) {
+            return U256::ZERO;
+        }
+        let now = self.vm().block_timestamp();
+        let from = loan
+            .next_payment_due
+            .get()
+            .max(loan.last_compounded_at.get())
+            .to::<u64>();
+        if now < from.saturating_add(period) {
+            return U256::ZERO;
+        }
+        let periods = ((now - from) / period).min(MAX_PERIODS);
+
+        let rate = U256::from(loan.interest_rate_bps.get());
+        let per_year = U256::from(SECONDS_PER_MONTH * u64::from(MONTHS_PER_YEAR))
+            * U256::from(BPS_DENOMINATOR);
+        let outstanding = loan.outstanding_balance.get();
+        let mut balance = outstanding;
+        for _ in 0..periods {
+            balance += balance * rate * U256::from(period) / per_year;
+        }
+
+        let mut loan = self.loans.setter(loan_id);
+        loan.outstanding_balance.set(balance);
+        let capitalized = loan.capitalized_interest.get();
+        loan.capitalized_interest.set(capitalized + balance - outstanding);
+        loan.last_compounded_at.set(U64::from(from + periods * period));
+        if loan.status.get() == U8::from(1) {
+            let active = self.active_outstanding.get();
+            self.active_outstanding.set(active + balance - outstanding);
+        }
+        balance - outstanding
+    }
+
+    // Re-spreads the outstanding balance over the amortizing months left at
+    // the loan's current rate. Call after anything that changes those terms.
+    // the longer first-payment grace applies until an installment is covered
+    fn _payment_grace(&self, loan_id: U256) -> U64 {
+        if self.loans.getter(loan_id).payments_made.get() == U32::ZERO {
+            self.first_payment_grace.get().max(self.grace_period.get())
+        } else {
+            self.grace_period.get()
+        }
+    }
+
+    fn _recompute_monthly_payment(&mut self, loan_id: U256) {
+        let loan = self.loans.getter(loan_id);
+        let outstanding = loan.outstanding_balance.get();
+        let rate_bps = loan.interest_rate_bps.get().to::<u32>();
+        let months_left = loan
+            .duration_months
+            .get()
+            .saturating_sub(loan.payments_made.get())
+            .saturating_sub(loan.interest_only_remaining.get());
+        let payment =
+            Self::_calculate_monthly_payment(outstanding, rate_bps, months_left.to::<u32>());
+        self.loans.setter(loan_id).monthly_payment.set(payment);
+    }
+
+    // lender deposit / pool liquidity of `amount`
+    fn _lender_share(&self, lender: Address, amount: U256) -> Result<U256, Vec<u8>> {
+        let pool = ILendingPool::new(self.lending_pool.get());
+        let (deposit, _, _, _) = pool.get_lender_info(self, lender)?;
+        let total_liquidity = pool.get_total_liquidity(self)?;
+        if total_liquidity == U256::ZERO {
+            return Ok(U256::ZERO);
+        }
+        Ok(amount * deposit / total_liquidity)
+    }
+
+    // (collateral value, outstanding balance) summed over active loans. Kept
+    // as running totals so no call walks every loan; collateral is valued
+    // when a loan turns active and again on refresh_aggregate_collateral.
+    fn _aggregate_position(&self) -> (U256, U256) {
+        (self.active_collateral_value.get(), self.active_outstanding.get())
+    }
+
+    // adds a loan that just turned active to the running totals
+    fn _enter_aggregate(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
+        let loan = self.loans.getter(loan_id);
+        let outstanding = loan.outstanding_balance.get();
+        let value = self.get_collateral_valuation(loan.nft_collateral_id.get())?;
+        self.collateral_snapshot.insert(loan_id, value);
+        let collateral_value = self.active_collateral_value.get();
+        self.active_collateral_value.set(collateral_value + value);
+        let active = self.active_outstanding.get();
+        self.active_outstanding.set(active + outstanding);
+        Ok(())
+    }
+
+    // takes a loan leaving Active back out, before its balance is zeroed
+    fn _leave_aggregate(&mut self, loan_id: U256) {
+        let value = self.collateral_snapshot.get(loan_id);
+        self.collateral_snapshot.insert(loan_id, U256::ZERO);
+        let collateral_value = self.active_collateral_value.get();
+        self.active_collateral_value.set(collateral_value.saturating_sub(value));
+        let outstanding = self.loans.getter(loan_id).outstanding_balance.get();
+        let active = self.active_outstanding.get();
+        self.active_outstanding.set(active.saturating_sub(outstanding));
+    }
+
+    // Whether liquidate accepts the loan now: defaulted, or active and flagged
+    // undercollateralized while still over the threshold, past the delay.
+    fn _check_liquidatable(&self, loan_id: U256) -> Result<(), Vec<u8>> {
+        if self.paused.get() {
+            return Err(b"Paused".to_vec());
+        }
+        let loan = self.loans.getter(loan_id);
+        let status = loan.status.get();
+        let flagged_at = loan.undercollateralized_at.get();
+        let since = if status == U8::from(3) {
+            loan.defaulted_at.get()
+        } else if status == U8::from(1) && flagged_at > U64::ZERO {
+            flagged_at
+        } else {
+            return Err(b"Loan not defaulted".to_vec());
+        };
+        let liquidatable_at = since.saturating_add(self.liquidation_delay_seconds.get());
+        if U64::from(self.vm().block_timestamp()) < liquidatable_at {
+            return Err(b"Liquidation delay not elapsed".to_vec());
+        }
+        if status == U8::from(1) && !self._above_liquidation_ltv(self.get_effective_ltv(loan_id)?) {
+            return Err(b"Loan not undercollateralized".to_vec());
+        }
+        Ok(())
+    }
+
+    // ids at positions [start, start + limit) of all_loan_ids
+    fn _loan_id_page(&self, start: U256, limit: U256) -> Vec<U256> {
+        let count = U256::from(self.all_loan_ids.len());
+        let end = start.saturating_add(limit).min(count);
+        let mut ids = Vec::new();
+        let mut index = start;
+        while index < end {
+            if let Some(loan_id) = self.all_loan_ids.get(index) {
+                ids.push(loan_id);
+            }
+            index += U256::from(1u64);
+        }
+        ids
+    }
+
+    fn _collateral_ratio(collateral_value: U256, outstanding: U256) -> U256 {
+        if outstanding == U256::ZERO {
+            return U256::MAX;
+        }
+        collateral_value.saturating_mul(U256::from(BPS_DENOMINATOR)) / outstanding
+    }
+
+    // Whether the system stays above min_aggregate_collateral_bps once a loan
+    // of `amount` backed by `nft_id` is added.
+    fn _aggregate_allows(&self, nft_id: U256, amount: U256) -> Result<bool, Vec<u8>> {
+        let min_ratio = self.min_aggregate_collateral_bps.get();
+        if min_ratio == U32::ZERO {
+            return Ok(true);
+        }
+        let (collateral_value, outstanding) = self._aggregate_position();
+        let value = self.get_collateral_valuation(nft_id)?;
+        let ratio = Self::_collateral_ratio(
+            collateral_value.saturating_add(value),
+            outstanding.saturating_add(amount),
+        );
+        Ok(ratio >= U256::from(min_ratio))
+    }
+
+    fn _above_liquidation_ltv(&self, ltv: U256) -> bool {
+        let threshold = self.liquidation_ltv_threshold_bps.get();
+        threshold > U32::ZERO && ltv > U256::from(threshold)
+    }
+
+    fn _clamp_rate(&self, rate_bps: u32) -> u32 {
+        rate_bps.clamp(self.min_rate_bps.get().to::<u32>(), self.max_rate_bps.get().to::<u32>())
+    }
+
+    // The borrower's open and pending balances plus `amount` must fit under the
+    // LTV limit of all their collateral valued together, each NFT counted once.
+    fn _within_aggregate_limit(
+        &self,
+        borrower: Address,
+        nft_id: U256,
+        amount: U256,
+        valuation: U256,
+    ) -> Result<bool, Vec<u8>> {
+        let nft = IRemittanceNFT::new(self.remittance_nft.get());
+        let mut exposure = amount;
+        let mut total_valuation = valuation;
+        let mut counted = vec![nft_id];
+
+        let loans = self.borrower_loans.getter(borrower);
+        for i in 0..loans.len() {
+            let Some(loan_id) = loans.get(i) else { continue };
+            let loan = self.loans.getter(loan_id);
+            let status = loan.status.get();
+            if status != U8::ZERO && status != U8::from(1) {
+                continue;
+            }
+            exposure += loan.outstanding_balance.get();
+
+            let loan_nft = loan.nft_collateral_id.get();
+            if !counted.contains(&loan_nft) {
+                let (_, monthly_amount, reliability_score, _, _) =
+                    nft.get_remittance(self, loan_nft)?;
+                total_valuation += Self::_collateral_valuation(monthly_amount, reliability_score);
+                counted.push(loan_nft);
+            }
+        }
+        Ok(exposure <= self._max_borrow(total_valuation))
+    }
+
+    fn _discounted_fee(&self, fee: U256, borrower: Address) -> U256 {
+        match self.get_loyalty_tier(borrower).to::<u8>() {
+            0 => fee,
+            1 => fee / U256::from(2u64),
+            _ => U256::ZERO,
+        }
+    }
+
+    // LTV cap less the safety buffer, so nobody borrows right up to the ceiling
+    fn _max_borrow(&self, valuation: U256) -> U256 {
+        let ltv_bps = self.max_ltv_bps.get().saturating_sub(self.safety_buffer_bps.get());
+        valuation * U256::from(ltv_bps) / U256::from(BPS_DENOMINATOR)
+    }
+
+    // Scores hold for two months after the last reported remittance, then lose
+    // reliability_decay_per_month points for every further month without one,
+    // down to zero. NFTs the oracle has no timestamp for are left as is.
+    fn _effective_reliability(&self, nft_id: U256, score: U256) -> Result<U256, Vec<u8>> {
+        let oracle = IOracleVerifier::new(self.oracle.get());
+        let last_update = oracle.get_last_remittance_at(self, nft_id)?;
+        if last_update == 0 {
+            return Ok(score);
+        }
+
+        let stale = self.vm().block_timestamp().saturating_sub(last_update);
+        let decay_months = stale.saturating_sub(2 * SECONDS_PER_MONTH) / SECONDS_PER_MONTH;
+        let penalty = U256::from(decay_months)
+            .saturating_mul(U256::from(self.reliability_decay_per_month.get()));
+        Ok(score.saturating_sub(penalty))
+    }
+
+    fn _eip712_domain(&self) -> Eip712Domain {
+        Eip712Domain::new(
+            Some("LoanManager".into()),
+            Some("1".into()),
+            Some(U256::from(self.vm().chain_id())),
+            Some(self.vm().contract_address()),
+            None,
+        )
+    }
+
+    // ecrecover precompile over a 65 byte r || s || v signature
+    fn _ecrecover(&self, digest: B256, signature: &[u8]) -> Result<Address, Vec<u8>> {
+        if signature.len() != 65 {
+            return Err(b"Invalid signature length".to_vec());
+        }
+        let v = if signature[64] < 27 { signature[64] + 27 } else { signature[64] };
+
+        let mut input = [0u8; 128];
+        input[..32].copy_from_slice(digest.as_slice());
+        input[63] = v;
+        input[64..].copy_from_slice(&signature[..64]);
+
+        let output = self
+            .vm()
+            .static_call(&self, Address::with_last_byte(1), &input)
+            .map_err(|_| b"ecrecover failed".to_vec())?;
+        if output.len() != 32 {
+            return Err(b"Invalid borrower signature".to_vec());
+        }
+        Ok(Address::from_slice(&output[12..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_hostio::*;
+    use stylus_sdk::testing::*;
+
+    // the calls this contract makes, as the other contracts export them
+    mod abi {
+        alloy_sol_types::sol! {
+            function getRemittance(uint256 token_id)
+                external
+                view
+                returns (address, uint256, uint256, uint256, bool);
+            function getLastRemittanceAt(uint256 nft_id) external view returns (uint64);
+            function getTotalLiquidity() external view returns (uint256);
+            function getAvailableLiquidity() external view returns (uint256);
+            function borrow(uint256 amount, address borrower, uint256 loan_id, uint32 rate_bps) external;
+            function unstakeNFT(uint256 token_id) external;
+            function transferFrom(address from, address to, uint256 token_id) external;
+        }
+    }
+
+    const ADMIN: Address = Address::repeat_byte(0xad);
+    const BORROWER: Address = Address::repeat_byte(0xb0);
+    const USDC: Address = Address::repeat_byte(0x0c);
+    const NFT: Address = Address::repeat_byte(0x2b);
+    const POOL: Address = Address::repeat_byte(0x9e);
+    const ORACLE: Address = Address::repeat_byte(0x0a);
+
+    fn setup() -> (TestVM, LoanManager) {
+        let vm = TestVM::default();
+        vm.set_sender(ADMIN);
+        vm.set_block_timestamp(1_700_000_000);
+        let mut manager = LoanManager::from(&vm);
+        manager.initialize(USDC).unwrap();
+        manager.setup_addresses(NFT, POOL, ORACLE).unwrap();
+        (vm, manager)
+    }
+
+    // an NFT owned by BORROWER with a year of $1,000 remittances behind it
+    fn mock_nft(nft_id: U256) {
+        let monthly = U256::from(1_000_000_000u64);
+        let remittance = (BORROWER, monthly, U256::from(90), monthly * U256::from(12), false);
+        let call = abi::getRemittanceCall { token_id: nft_id }.abi_encode();
+        mock_call(NFT, call, Ok(remittance.abi_encode_params()));
+        let call = abi::getLastRemittanceAtCall { nft_id }.abi_encode();
+        mock_call(ORACLE, call, Ok(0u64.abi_encode()));
+    }
+
+    fn request(vm: &TestVM, manager: &mut LoanManager, nft_id: U256, amount: U256) -> U256 {
+        mock_nft(nft_id);
+        vm.set_sender(BORROWER);
+        let loan_id = manager
+            .request_loan(nft_id, amount, 12, 0, B256::ZERO, false)
+            .unwrap();
+        vm.set_sender(ADMIN);
+        loan_id
+    }
+
+    #[test]
+    fn aggregate_collateral_tracks_approvals_and_blocks_below_minimum() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+        let first = request(&vm, &mut manager, U256::from(1), amount);
+        let value = manager.get_collateral_valuation(U256::from(1)).unwrap();
+        assert_eq!(manager.get_aggregate_collateral_ratio(), U256::MAX);
+
+        manager.approve_loan(first).unwrap();
+        let ratio = value * U256::from(BPS_DENOMINATOR) / amount;
+        assert_eq!(manager.get_aggregate_collateral_ratio(), ratio);
+        mock_call(POOL, abi::getTotalLiquidityCall {}.abi_encode(), Ok(amount.abi_encode()));
+        assert_eq!(manager.get_tvl().unwrap(), (amount, value));
+
+        // a second loan of twice the size on the same collateral value drags
+        // the system ratio under where it stands now
+        manager.set_min_aggregate_collateral(ratio.to::<u32>()).unwrap();
+        let second = request(&vm, &mut manager, U256::from(2), amount * U256::from(2));
+        assert_eq!(
+            manager.approve_loan(second),
+            Err(b"Aggregate collateral too low".to_vec())
+        );
+        assert_eq!(manager.get_loan_status(second), U8::from(0));
+
+        // blocking the approval leaves the totals as they were
+        assert_eq!(manager.get_aggregate_collateral_ratio(), ratio);
+    }
+
+    #[test]
+    fn nft_backs_one_loan_at_a_time() {
+        let (vm, mut manager) = setup();
+        let nft_id = U256::from(1);
+        let amount = U256::from(100_000_000u64);
+        let first = request(&vm, &mut manager, nft_id, amount);
+
+        vm.set_sender(BORROWER);
+        assert_eq!(
+            manager.request_loan(nft_id, amount, 12, 0, B256::ZERO, false),
+            Err(b"NFT already collateral".to_vec())
+        );
+
+        // once the first request expires the NFT is free again
+        vm.set_block_timestamp(vm.block_timestamp() + 30 * SECONDS_PER_DAY);
+        manager.expire_pending_loan(first).unwrap();
+        let second = manager
+            .request_loan(nft_id, amount, 12, 0, B256::ZERO, false)
+            .unwrap();
+        assert_eq!(manager.get_collateral_status(nft_id), (U8::ZERO, U256::ZERO));
+
+        // and approving the new loan maps it there
+        vm.set_sender(ADMIN);
+        manager.approve_loan(second).unwrap();
+        assert_eq!(manager.get_collateral_status(nft_id), (U8::from(1), second));
+    }
+
+    #[test]
+    fn cure_rolls_the_due_date_past_every_missed_month() {
+        let (vm, mut manager) = setup();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        let (.., monthly, _, due, _, _, _) = manager.get_loan(loan_id);
+
+        vm.set_sender(ORACLE);
+        vm.set_block_timestamp(due.to::<u64>() + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, ADMIN).unwrap();
+        // the same due date can't be counted twice
+        assert_eq!(
+            manager.mark_payment_missed(loan_id, ADMIN),
+            Err(b"Payment not overdue".to_vec())
+        );
+        vm.set_block_timestamp(due.to::<u64>() + PAYMENT_PERIOD + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, ADMIN).unwrap();
+        assert_eq!(manager.get_loan_status(loan_id), U8::from(3));
+
+        vm.set_sender(BORROWER);
+        manager.cure_default(loan_id, monthly * U256::from(2)).unwrap();
+        let (.., cured_monthly, _, cured_due, status, _, missed) = manager.get_loan(loan_id);
+        assert_eq!(status, U8::from(1));
+        assert_eq!(missed, U32::ZERO);
+        assert_eq!(cured_due, due + U64::from(2 * PAYMENT_PERIOD));
+        // paying the arrears isn't a prepayment, the schedule stays as it was
+        assert_eq!(cured_monthly, monthly);
+    }
+
+    #[test]
+    fn each_installment_moves_the_due_date_thirty_days() {
+        let (vm, mut manager) = setup();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        let (.., monthly, _, due, _, _, _) = manager.get_loan(loan_id);
+
+        vm.set_sender(BORROWER);
+        manager.make_payment(loan_id, monthly).unwrap();
+        let (.., next_due, _, _, _) = manager.get_loan(loan_id);
+        assert_eq!(PAYMENT_PERIOD, 2_592_000);
+        assert_eq!(next_due, due + U64::from(PAYMENT_PERIOD));
+
+        manager.payoff_loan(loan_id).unwrap();
+        let (.., closed_due, status, _, _) = manager.get_loan(loan_id);
+        assert_eq!(status, U8::from(2));
+        assert_eq!(closed_due, next_due);
+    }
+
+    #[test]
+    fn default_reward_goes_to_the_reporting_keeper_once() {
+        let (vm, mut manager) = setup();
+        let reward = U256::from(5_000_000u64);
+        manager.set_keeper_reward(reward).unwrap();
+        manager.fund_keeper_rewards(reward * U256::from(10)).unwrap();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        let (.., due, _, _, _) = manager.get_loan(loan_id);
+
+        let keeper = Address::repeat_byte(0x4e);
+        vm.set_sender(ORACLE);
+        vm.set_block_timestamp(due.to::<u64>() + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, keeper).unwrap();
+        assert!(manager.mark_payment_missed(loan_id, keeper).is_err());
+        assert_eq!(manager.get_keeper_earnings(keeper), U256::ZERO);
+        vm.set_block_timestamp(due.to::<u64>() + PAYMENT_PERIOD + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, keeper).unwrap();
+        assert_eq!(manager.get_keeper_earnings(keeper), reward);
+        assert_eq!(manager.get_keeper_earnings(ORACLE), U256::ZERO);
+
+        // curing and defaulting again doesn't pay for the same loan twice
+        let (.., monthly, _, _, _, _, _) = manager.get_loan(loan_id);
+        vm.set_sender(BORROWER);
+        manager.cure_default(loan_id, monthly * U256::from(2)).unwrap();
+        let (.., due, _, _, _) = manager.get_loan(loan_id);
+        vm.set_sender(ORACLE);
+        vm.set_block_timestamp(due.to::<u64>() + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, keeper).unwrap();
+        vm.set_block_timestamp(due.to::<u64>() + PAYMENT_PERIOD + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, keeper).unwrap();
+        assert_eq!(manager.get_loan_status(loan_id), U8::from(3));
+        assert_eq!(manager.get_keeper_earnings(keeper), reward);
+    }
+
+    #[test]
+    fn preview_follows_liquidate_for_flagged_active_loans() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+        let loan_id = request(&vm, &mut manager, U256::from(1), amount);
+        manager.approve_loan(loan_id).unwrap();
+        manager.set_liquidation_ltv_threshold(1).unwrap();
+        assert!(manager.check_and_flag_undercollateralized(loan_id).unwrap());
+
+        // flagged but still inside the delay: neither goes through
+        assert_eq!(manager.preview_liquidation(loan_id, amount), Ok((U256::ZERO, false)));
+        assert!(manager.liquidate(loan_id).is_err());
+
+        vm.set_block_timestamp(vm.block_timestamp() + 14 * SECONDS_PER_DAY);
+        assert_eq!(manager.preview_liquidation(loan_id, amount), Ok((amount, true)));
+        manager.liquidate(loan_id).unwrap();
+        assert_eq!(manager.get_loan_status(loan_id), U8::from(4));
+    }
+
+    #[test]
+    fn borrower_loans_list_every_request_in_order() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+        let first = request(&vm, &mut manager, U256::from(1), amount);
+        let second = request(&vm, &mut manager, U256::from(2), amount);
+
+        assert_eq!(manager.get_borrower_loans(BORROWER), vec![first, second]);
+        assert!(manager.get_borrower_loans(ADMIN).is_empty());
+    }
+
+    #[test]
+    fn payments_accumulate_repayment_history() {
+        let (vm, mut manager) = setup();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        let (.., monthly, _, _, _, _, _) = manager.get_loan(loan_id);
+
+        let history = |manager: &LoanManager| {
+            let (_, _, _, _, _, total_repaid, .., payments_made, _) = manager.get_loan(loan_id);
+            (total_repaid, payments_made)
+        };
+
+        // half an installment is repaid but doesn't cover one yet
+        vm.set_sender(BORROWER);
+        let half = monthly / U256::from(2);
+        manager.make_payment(loan_id, half).unwrap();
+        assert_eq!(history(&manager), (half, U32::ZERO));
+        manager.make_payment(loan_id, monthly - half).unwrap();
+        assert_eq!(history(&manager), (monthly, U32::from(1)));
+        manager.make_payment(loan_id, monthly).unwrap();
+        assert_eq!(history(&manager), (monthly * U256::from(2), U32::from(2)));
+
+        // the payoff closes the loan and counts as the last installment
+        manager.payoff_loan(loan_id).unwrap();
+        let payments = manager.get_payment_history(loan_id);
+        assert_eq!(payments.len(), 4);
+        let (_, payoff, _, _) = payments[3];
+        assert_eq!(history(&manager), (monthly * U256::from(2) + payoff, U32::from(3)));
+        assert_eq!(manager.get_loan_status(loan_id), U8::from(2));
+    }
+
+    #[test]
+    fn failed_borrow_leaves_the_loan_pending_for_retry() {
+        let (vm, mut manager) = setup();
+        let nft_id = U256::from(1);
+        let amount = U256::from(100_000_000u64);
+        let loan_id = request(&vm, &mut manager, nft_id, amount);
+        let (.., rate_bps, _, _, _, _, _, _, _) = manager.get_loan(loan_id);
+        let borrow = abi::borrowCall {
+            amount,
+            borrower: BORROWER,
+            loan_id,
+            rate_bps: rate_bps.to::<u32>(),
+        }
+        .abi_encode();
+
+        mock_call(POOL, borrow.clone(), Err(b"Insufficient liquidity".to_vec()));
+        assert_eq!(
+            manager.approve_loan(loan_id),
+            Err(b"Approval failed: pool liquidity".to_vec())
+        );
+        assert_eq!(manager.get_loan_status(loan_id), U8::ZERO);
+        assert_eq!(manager.get_collateral_status(nft_id), (U8::ZERO, U256::ZERO));
+        assert_eq!(manager.get_aggregate_collateral_ratio(), U256::MAX);
+
+        mock_call(POOL, borrow, Ok(Vec::new()));
+        manager.approve_loan(loan_id).unwrap();
+        assert_eq!(manager.get_collateral_status(nft_id), (U8::from(1), loan_id));
+    }
+
+    #[test]
+    fn term_changes_recompute_the_monthly_payment() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+        let loan_id = request(&vm, &mut manager, U256::from(1), amount);
+        manager.approve_loan(loan_id).unwrap();
+        let (.., rate_bps, _, monthly, _, _, _, _, _) = manager.get_loan(loan_id);
+        let rate_bps = rate_bps.to::<u32>();
+
+        // a longer term spreads the same balance thinner
+        manager.extend_term(loan_id, 6).unwrap();
+        let (.., extended, _, _, _, _, _) = manager.get_loan(loan_id);
+        assert_eq!(extended, LoanManager::_calculate_monthly_payment(amount, rate_bps, 18));
+        assert!(extended < monthly);
+
+        // paying ahead re-spreads what's left over the remaining months
+        vm.set_sender(BORROWER);
+        manager.make_payment(loan_id, extended * U256::from(2)).unwrap();
+        let (_, _, _, _, outstanding, .., prepaid, _, _, _, _, _) = manager.get_loan(loan_id);
+        assert_eq!(prepaid, LoanManager::_calculate_monthly_payment(outstanding, rate_bps, 17));
+        assert!(prepaid < extended);
+    }
+
+    #[test]
+    fn schedule_dates_use_one_payment_period() {
+        assert_eq!(SECONDS_PER_MONTH * u64::from(MONTHS_PER_YEAR), 31_556_952);
+
+        let (vm, mut manager) = setup();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        let (.., monthly, start, due, _, _, _) = manager.get_loan(loan_id);
+        assert_eq!(due, start + U64::from(PAYMENT_PERIOD));
+        let completion = start + U64::from(12 * PAYMENT_PERIOD);
+        assert_eq!(manager.get_expected_completion(loan_id), completion);
+
+        // a year of installments ends exactly where the completion says
+        vm.set_sender(BORROWER);
+        for _ in 0..11 {
+            manager.make_payment(loan_id, monthly).unwrap();
+        }
+        let (.., last_due, _, _, _) = manager.get_loan(loan_id);
+        assert_eq!(last_due, completion);
+    }
+
+    #[test]
+    fn payment_made_decodes_with_its_indexed_loan() {
+        let (vm, mut manager) = setup();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        let (.., monthly, _, _, _, _, _) = manager.get_loan(loan_id);
+
+        vm.set_sender(BORROWER);
+        manager.make_payment(loan_id, monthly).unwrap();
+        let (topics, data) = vm
+            .get_emitted_logs()
+            .into_iter()
+            .find(|(topics, _)| topics[0] == <PaymentMade as alloy_sol_types::SolEvent>::SIGNATURE_HASH)
+            .unwrap();
+        assert_eq!(topics.len(), 2);
+        let event = <PaymentMade as alloy_sol_types::SolEvent>::decode_raw_log(topics, &data, true).unwrap();
+        assert_eq!((event.loan_id, event.amount), (loan_id, monthly));
+    }
+
+    #[test]
+    fn settling_as_defaulted_keeps_the_collateral() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+        let repaid = request(&vm, &mut manager, U256::from(1), amount);
+        let defaulted = request(&vm, &mut manager, U256::from(2), amount);
+        manager.approve_loan(repaid).unwrap();
+        manager.approve_loan(defaulted).unwrap();
+
+        manager.pause().unwrap();
+        manager
+            .settle_loans(vec![repaid, defaulted], vec![U8::from(2), U8::from(3)])
+            .unwrap();
+        assert_eq!(manager.get_collateral_status(U256::from(1)), (U8::ZERO, U256::ZERO));
+        assert_eq!(manager.get_collateral_status(U256::from(2)), (U8::from(2), defaulted));
+    }
+
+    #[test]
+    fn write_off_keeps_the_nft_staked_and_frees_the_mappings() {
+        let (vm, mut manager) = setup();
+        let nft_id = U256::from(1);
+        let loan_id = request(&vm, &mut manager, nft_id, U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        manager.pause().unwrap();
+        manager.settle_loans(vec![loan_id], vec![U8::from(3)]).unwrap();
+        manager.unpause().unwrap();
+
+        // the NFT is neither unstaked nor moved
+        let unstake = abi::unstakeNFTCall { token_id: nft_id }.abi_encode();
+        mock_call(NFT, unstake, Err(b"unstaked".to_vec()));
+        let transfer = abi::transferFromCall { from: BORROWER, to: POOL, token_id: nft_id };
+        mock_call(NFT, transfer.abi_encode(), Err(b"transferred".to_vec()));
+
+        manager.set_write_off_period(0).unwrap();
+        manager.write_off(loan_id).unwrap();
+        assert_eq!(manager.get_loan_status(loan_id), U8::from(5));
+        assert_eq!(manager.get_collateral_status(nft_id), (U8::ZERO, U256::ZERO));
+        assert_eq!(
+            manager.rescue_collateral(loan_id),
+            Err(b"Loan not closed".to_vec())
+        );
+    }
+
+    #[test]
+    fn cure_needs_real_arrears_charged_like_payments() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+        mock_nft(U256::from(1));
+        vm.set_sender(BORROWER);
+        let loan_id = manager
+            .request_loan(U256::from(1), amount, 12, 3, B256::ZERO, false)
+            .unwrap();
+        vm.set_sender(ADMIN);
+        manager.approve_loan(loan_id).unwrap();
+        let (.., rate_bps, _, _, _, due, _, _, _) = manager.get_loan(loan_id);
+        let interest = LoanManager::_calculate_interest_portion(amount, rate_bps, false);
+
+        vm.set_sender(ORACLE);
+        vm.set_block_timestamp(due.to::<u64>() + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, ADMIN).unwrap();
+        vm.set_block_timestamp(due.to::<u64>() + PAYMENT_PERIOD + 15 * SECONDS_PER_DAY);
+        manager.mark_payment_missed(loan_id, ADMIN).unwrap();
+
+        // two interest-only months are owed, one month's interest won't do
+        vm.set_sender(BORROWER);
+        assert_eq!(
+            manager.cure_default(loan_id, interest),
+            Err(b"Amount below arrears".to_vec())
+        );
+        manager.cure_default(loan_id, interest * U256::from(2)).unwrap();
+        let (.., outstanding, _, _, _, _, _, cured_due, status, _, _) = manager.get_loan(loan_id);
+        assert_eq!(status, U8::from(1));
+        assert_eq!(outstanding, amount);
+        assert_eq!(cured_due, due + U64::from(2 * PAYMENT_PERIOD));
+        assert_eq!(manager.get_interest_only_months(loan_id), (U32::from(3), U32::from(1)));
+
+        // a loan defaulted with nothing missed isn't curable
+        vm.set_sender(ADMIN);
+        manager.pause().unwrap();
+        manager.settle_loans(vec![loan_id], vec![U8::from(3)]).unwrap();
+        manager.unpause().unwrap();
+        vm.set_sender(BORROWER);
+        assert_eq!(
+            manager.cure_default(loan_id, U256::from(1)),
+            Err(b"No missed payments".to_vec())
+        );
+    }
+
+    #[test]
+    fn anomalous_remittances_are_all_held_from_the_first_report() {
+        let (vm, mut manager) = setup();
+        let loan_id = request(&vm, &mut manager, U256::from(1), U256::from(100_000_000u64));
+        manager.approve_loan(loan_id).unwrap();
+        vm.set_sender(BORROWER);
+        manager.set_auto_repay(loan_id, true).unwrap();
+        let monthly = U256::from(1_000_000_000u64);
+        assert_eq!(manager.get_remittance_stats(loan_id), (monthly, U256::ZERO));
+
+        // the very first report is already measured against the NFT's history,
+        // and a second one adds to what is held rather than replacing it
+        let (first, second) = (monthly * U256::from(6), monthly * U256::from(7));
+        vm.set_sender(ORACLE);
+        assert_eq!(manager.process_auto_repayment(loan_id, first), Ok(first));
+        assert_eq!(manager.process_auto_repayment(loan_id, second), Ok(second));
+        assert_eq!(manager.get_remittance_stats(loan_id), (monthly, first + second));
+
+        vm.set_sender(ADMIN);
+        assert_eq!(manager.resolve_held_remittance(loan_id, false), Ok(first + second));
+        assert_eq!(manager.get_remittance_stats(loan_id), (monthly, U256::ZERO));
+    }
+
+    #[test]
+    fn loan_lifecycle_events_decode() {
+        let (vm, mut manager) = setup();
+        let reward = U256::from(5_000_000u64);
+        manager.set_keeper_reward(reward).unwrap();
+        manager.fund_keeper_rewards(reward).unwrap();
+        let nft_id = U256::from(1);
+        let loan_id = request(&vm, &mut manager, nft_id, U256::from(100_000_000u64));
+        let requested = last_event::<LoanRequested>(&vm);
+        assert_eq!((requested.borrower, requested.loan_id), (BORROWER, loan_id));
+        assert_eq!(requested.external_ref, B256::ZERO);
+
+        manager.approve_loan(loan_id).unwrap();
+        assert_eq!(last_event::<LoanApproved>(&vm).loan_id, loan_id);
+        manager.extend_term(loan_id, 6).unwrap();
+        let extended = last_event::<LoanTermExtended>(&vm);
+        assert_eq!((extended.loan_id, extended.additional_months), (loan_id, 6));
+
+        let (.., monthly, _, due, _, _, _) = manager.get_loan(loan_id);
+        let keeper = Address::repeat_byte(0x4e);
+        vm.set_sender(ORACLE);
+        for missed in 1..=2u32 {
+            let late = u64::from(missed - 1) * PAYMENT_PERIOD + 15 * SECONDS_PER_DAY;
+            vm.set_block_timestamp(due.to::<u64>() + late);
+            manager.mark_payment_missed(loan_id, keeper).unwrap();
+            let event = last_event::<PaymentMissed>(&vm);
+            assert_eq!((event.loan_id, event.missed_count), (loan_id, missed));
+        }
+        let rewarded = last_event::<KeeperRewarded>(&vm);
+        assert_eq!((rewarded.keeper, rewarded.action, rewarded.amount), (keeper, 0, reward));
+
+        vm.set_sender(BORROWER);
+        manager.cure_default(loan_id, monthly * U256::from(2)).unwrap();
+        assert_eq!(last_event::<LoanCured>(&vm).loan_id, loan_id);
+        let paid = last_event::<PaymentMade>(&vm);
+        assert_eq!((paid.loan_id, paid.amount), (loan_id, monthly * U256::from(2)));
+
+        vm.set_sender(ADMIN);
+        manager.pause().unwrap();
+        manager.settle_loans(vec![loan_id], vec![U8::from(3)]).unwrap();
+        let changed = last_event::<LoanStatusChanged>(&vm);
+        assert_eq!((changed.loan_id, changed.status), (loan_id, 3));
+        manager.set_write_off_period(0).unwrap();
+        manager.write_off(loan_id).unwrap();
+        let (_, _, _, _, outstanding, ..) = manager.get_loan(loan_id);
+        let written_off = last_event::<LoanWrittenOff>(&vm);
+        assert_eq!(written_off.loan_id, loan_id);
+        assert!(written_off.loss > outstanding);
+    }
+
+    #[test]
+    fn risk_and_admin_events_decode() {
+        let (vm, mut manager) = setup();
+        let amount = U256::from(100_000_000u64);
+
+        // an undercollateralized loan is flagged, then liquidated
+        let flagged = request(&vm, &mut manager, U256::from(1), amount);
+        manager.approve_loan(flagged).unwrap();
+        manager.set_liquidation_ltv_threshold(1).unwrap();
+        manager.check_and_flag_undercollateralized(flagged).unwrap();
+        let under = last_event::<LoanUndercollateralized>(&vm);
+        assert_eq!(under.loan_id, flagged);
+        assert_eq!(under.ltv, manager.get_effective_ltv(flagged).unwrap());
+        vm.set_block_timestamp(vm.block_timestamp() + 14 * SECONDS_PER_DAY);
+        manager.liquidate(flagged).unwrap();
+        let liquidated = last_event::<LoanLiquidated>(&vm);
+        assert_eq!((liquidated.loan_id, liquidated.outstanding), (flagged, amount));
+
+        // an outsized remittance is held, a stale request expires
+        let held = request(&vm, &mut manager, U256::from(2), amount);
+        manager.approve_loan(held).unwrap();
+        vm.set_sender(BORROWER);
+        manager.set_auto_repay(held, true).unwrap();
+        let remittance = U256::from(10_000_000_000u64);
+        vm.set_sender(ORACLE);
+        manager.process_auto_repayment(held, remittance).unwrap();
+        let blocked = last_event::<AnomalousRemittanceBlocked>(&vm);
+        assert_eq!((blocked.loan_id, blocked.amount), (held, remittance));
+        let stale = request(&vm, &mut manager, U256::from(3), amount);
+        vm.set_block_timestamp(vm.block_timestamp() + 30 * SECONDS_PER_DAY);
+        manager.expire_pending_loan(stale).unwrap();
+        assert_eq!(last_event::<LoanExpired>(&vm).loan_id, stale);
+
+        // handing over the oracle and the token
+        let oracle = Address::repeat_byte(0x0b);
+        manager.propose_oracle(oracle).unwrap();
+        assert_eq!(last_event::<OracleProposed>(&vm).oracle, oracle);
+        vm.set_sender(oracle);
+        manager.accept_oracle().unwrap();
+        assert_eq!(last_event::<OracleAccepted>(&vm).oracle, oracle);
+        vm.set_sender(ADMIN);
+        manager.pause().unwrap();
+        mock_call(POOL, abi::getTotalLiquidityCall {}.abi_encode(), Ok(amount.abi_encode()));
+        let available = abi::getAvailableLiquidityCall {}.abi_encode();
+        mock_call(POOL, available, Ok(amount.abi_encode()));
+        let token = Address::repeat_byte(0x0d);
+        manager.migrate_token(token).unwrap();
+        let migrated = last_event::<TokenMigrated>(&vm);
+        assert_eq!((migrated.old_token, migrated.new_token), (USDC, token));
+        let authority = Address::repeat_byte(0x5a);
+        manager.set_approval_authority(authority).unwrap();
+        assert_eq!(last_event::<ApprovalAuthorityChanged>(&vm).authority, authority);
     }
 }
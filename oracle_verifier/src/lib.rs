@@ -5,12 +5,18 @@
 extern crate alloc;
 
 use alloc::{string::String, vec::Vec};
-use alloy_sol_types::{sol, SolEvent};
+use alloy_sol_types::{sol, SolCall, SolValue};
 use stylus_sdk::{
-    alloy_primitives::{Address, U256, U32, U64, U8},
+    alloy_primitives::{Address, B256, U256, U32, U64, U8},
+    crypto::keccak,
     prelude::*,
+    stylus_core::{calls::context::Call, log},
 };
 
+#[cfg(test)]
+#[path = "../../test_hostio.rs"]
+mod test_hostio;
+
 sol_interface! {
     interface IRemittanceNFT {
         function mint(
@@ -28,11 +34,24 @@ sol_interface! {
         ) external;
 
         function unstake_nft(uint256 token_id) external;
+
+        function getRemittance(uint256 token_id)
+            external
+            view
+            returns (address, uint256, uint256, uint256, bool);
     }
 
     interface ILoanManager {
-        function process_auto_repayment(uint256 loan_id, uint256 amount) external;
-        function mark_payment_missed(uint256 loan_id) external;
+        function processAutoRepayment(uint256 loan_id, uint256 amount)
+            external
+            returns (uint256);
+        function getOutstandingBalance(uint256 loan_id) external view returns (uint256);
+        function markPaymentMissed(uint256 loan_id, address keeper) external;
+        function getLoanStatus(uint256 loan_id) external view returns (uint8);
+        function getLoanCollateral(uint256 loan_id) external view returns (uint256);
+        function acceptOracle() external;
+        function getCurrentShortfall(uint256 loan_id) external view returns (uint256);
+        function isPaymentOverdue(uint256 loan_id) external view returns (bool);
     }
 }
 
@@ -45,6 +64,35 @@ sol_storage! {
         // address[] oracle_operators;
         mapping(address => VerificationRequest) verification_requests;
         mapping(uint256 => bool) monitored_loans;
+        mapping(uint256 => uint64) last_remittance_at;
+        uint32 no_history_score;
+        uint256 min_remittance_for_repayment;
+        uint256 max_remittance_per_report;
+        mapping(uint256 => uint256) rejected_remittances; // per loan, reports turned away by the cap
+        uint32 scoring_model_version;
+        mapping(uint256 => uint32) nft_scoring_version;
+        address usdc;
+        uint256 operator_bond;
+        uint64 bond_cooldown;
+        mapping(address => Operator) operators;
+        mapping(bytes32 => ProviderConfig) provider_configs;
+        uint256 fee_balance;
+        uint256 verification_fee; // charged on every request on top of the provider fee
+        mapping(address => uint256) operator_fees;
+        // running totals of what the contract holds in usdc for others
+        uint256 total_bonds;
+        uint256 escrowed_fees; // fee_paid + operator_fee_paid over pending requests
+        uint256 total_operator_fees;
+    }
+    pub struct Operator {
+        bool active;
+        uint256 bond;
+        uint64 removed_at;
+    }
+    pub struct ProviderConfig {
+        bool configured;
+        uint32 trust_weight_bps; // scales the score, BPS_DENOMINATOR leaves it as is
+        uint256 verification_fee;
     }
     pub struct VerificationRequest {
         address user;
@@ -52,18 +100,50 @@ sol_storage! {
         string account_id;
         uint64 request_timestamp;
         uint8 status; // 0=Pending,1=Verified,2=Failed
+        uint32 scoring_model_version;
+        uint256 fee_paid;
+        uint256 operator_fee_paid; // verification_fee share, owed to whoever processes it
     }
 }
 
 sol! {
-    event VerificationRequested(address indexed user);
-    event VerificationComplete(address indexed user, uint256 reliability_score);
+    // encoded by hand so tokens that return nothing (USDT) still work
+    function transfer(address to, uint256 value) external returns (bool);
+    function transferFrom(address from, address to, uint256 value) external returns (bool);
+
+    event VerificationRequested(address indexed user, bytes32 indexed provider_hash);
+    event VerificationComplete(
+        address indexed user,
+        bytes32 indexed provider_hash,
+        uint256 reliability_score
+    );
     event MonitoringStarted(uint256 indexed loan_id);
-    event RemittanceReported(uint256 indexed loan_id, uint256 indexed nft_id, uint256 amount);
+    event RemittanceReported(
+        uint256 indexed loan_id,
+        uint256 indexed nft_id,
+        uint256 amount,
+        uint256 outstanding_balance,
+        uint256 leftover
+    );
     event PaymentMissedReported(uint256 indexed loan_id, uint256 indexed nft_id);
+    event LoanDefaultReported(uint256 indexed loan_id, uint256 indexed nft_id);
+    event MaxRemittancePerReportUpdated(uint256 cap);
+    event RemittanceRejected(uint256 indexed loan_id, uint256 indexed nft_id, uint256 amount, uint256 cap);
+    event ProviderConfigured(
+        bytes32 indexed provider_hash,
+        uint32 trust_weight_bps,
+        uint256 verification_fee
+    );
+    event VerificationFailed(address indexed user, uint256 refund);
+    event OperatorAdded(address indexed operator, uint256 bond);
+    event OperatorRemoved(address indexed operator);
+    event OperatorSlashed(address indexed operator, uint256 amount, uint8 reason_code);
     event Created(address indexed admin);
 }
 
+const BPS_DENOMINATOR: u32 = 10_000;
+const BOND_COOLDOWN_DEFAULT: u64 = 7 * 24 * 60 * 60;
+
 #[public]
 impl OracleVerifier {
     #[constructor]
@@ -72,6 +152,9 @@ impl OracleVerifier {
             return Err(b"".to_vec());
         }
         self.admin.set(self.vm().msg_sender());
+        self.no_history_score.set(U32::from(50));
+        self.scoring_model_version.set(U32::from(1));
+        self.bond_cooldown.set(U64::from(BOND_COOLDOWN_DEFAULT));
 
         // self.vm().emit_log(
         //     &Created {
@@ -88,15 +171,98 @@ impl OracleVerifier {
         remittance_nft: Address,
         loan_manager: Address,
     ) -> Result<(), Vec<u8>> {
-        // if self.admin.get() != self.vm().msg_sender() {
-        //     return Err(b"Only admin can set addresses".to_vec());
-        // }
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin can set addresses".to_vec());
+        }
 
         self.remittance_nft.set(remittance_nft);
         self.loan_manager.set(loan_manager);
         Ok(())
     }
 
+    // confirms this contract as the loan manager's oracle after it was proposed
+    pub fn accept_loan_manager_oracle(&mut self) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        let loan_mgr = ILoanManager::new(self.loan_manager.get());
+        loan_mgr.accept_oracle(&mut *self)?;
+        Ok(())
+    }
+
+    pub fn set_no_history_score(&mut self, score: u32) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if score > 100 {
+            return Err(b"Invalid score".to_vec());
+        }
+        self.no_history_score.set(U32::from(score));
+        self._bump_scoring_model_version();
+        Ok(())
+    }
+
+    pub fn get_no_history_score(&self) -> U32 {
+        self.no_history_score.get()
+    }
+
+    // remittances below this skip auto-repayment and stay with the borrower
+    pub fn set_min_remittance_for_repayment(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.min_remittance_for_repayment.set(amount);
+        Ok(())
+    }
+
+    pub fn get_min_remittance_for_repayment(&self) -> U256 {
+        self.min_remittance_for_repayment.get()
+    }
+
+    // upper bound on a single report's amount, 0 leaves it uncapped
+    pub fn set_max_remittance_per_report(&mut self, cap: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.max_remittance_per_report.set(cap);
+        log(self.vm(), MaxRemittancePerReportUpdated { cap });
+        Ok(())
+    }
+
+    pub fn get_max_remittance_per_report(&self) -> U256 {
+        self.max_remittance_per_report.get()
+    }
+
+    // how many of a loan's reports were over the cap and left unapplied
+    pub fn get_rejected_remittances(&self, loan_id: U256) -> U256 {
+        self.rejected_remittances.get(loan_id)
+    }
+
+    // bumped whenever the scoring inputs change, so scores stay comparable
+    pub fn get_scoring_model_version(&self) -> U32 {
+        self.scoring_model_version.get()
+    }
+
+    // model version that scored the NFT at mint, 0 if unknown
+    pub fn get_nft_scoring_version(&self, nft_id: U256) -> U32 {
+        self.nft_scoring_version.get(nft_id)
+    }
+
+    pub fn get_request_scoring_version(&self, user: Address) -> U32 {
+        self.verification_requests.get(user).scoring_model_version.get()
+    }
+
+    // (no_history_score, min_remittance_for_repayment, scoring_model_version,
+    //  max_remittance_per_report)
+    pub fn get_parameters(&self) -> (U32, U256, U32, U256) {
+        (
+            self.no_history_score.get(),
+            self.min_remittance_for_repayment.get(),
+            self.scoring_model_version.get(),
+            self.max_remittance_per_report.get(),
+        )
+    }
+
     pub fn request_verification(
         &mut self,
         provider: String,
@@ -104,6 +270,22 @@ impl OracleVerifier {
     ) -> Result<(), Vec<u8>> {
         let user = self.vm().msg_sender();
         let timestamp = U64::from(self.vm().block_timestamp());
+        let provider_hash = keccak(provider.as_bytes());
+
+        // a pending request's fees are still held for it
+        let existing = self.verification_requests.getter(user);
+        if existing.user.get() != Address::ZERO && existing.status.get() == U8::from(0) {
+            return Err(b"Verification already pending".to_vec());
+        }
+
+        // both fees are escrowed until the request is processed: earned by the
+        // protocol and operator on success, refunded on failure
+        let fee = self.provider_configs.getter(provider_hash).verification_fee.get();
+        let operator_fee = self.verification_fee.get();
+        if fee + operator_fee > U256::ZERO {
+            let contract = self.vm().contract_address();
+            self._safe_transfer_from(user, contract, fee + operator_fee)?;
+        }
 
         let mut request = self.verification_requests.setter(user);
         request.user.set(user);
@@ -111,8 +293,10 @@ impl OracleVerifier {
         request.account_id.set_str(account_id);
         request.request_timestamp.set(timestamp);
         request.status.set(U8::from(0)); // Pending
-        self.vm()
-            .emit_log(&VerificationRequested { user }.encode_data(), 2);
+        request.fee_paid.set(fee);
+        request.operator_fee_paid.set(operator_fee);
+        self.escrowed_fees.set(self.escrowed_fees.get() + fee + operator_fee);
+        log(self.vm(), VerificationRequested { user, provider_hash });
         Ok(())
     }
 
@@ -130,18 +314,28 @@ impl OracleVerifier {
         paid_count: U32,
         total_count: U32,
     ) -> Result<(), Vec<u8>> {
+        let operator = self.vm().msg_sender();
+        if !self.can_report(operator) {
+            return Err(b"Only operator".to_vec());
+        }
         let request = self.verification_requests.get(user);
         if request.status.get() != U8::from(0) {
             return Err(b"Already processed".to_vec());
         }
+        let provider_hash = keccak(request.provider.get_string().as_bytes());
 
-        let reliability_score = Self::_calculate_reliability_score(paid_count, total_count);
+        let reliability_score = Self::_calculate_reliability_score(
+            paid_count,
+            total_count,
+            self.no_history_score.get(),
+        );
+        let reliability_score = self._apply_trust_weight(provider_hash, reliability_score);
         let remittance_nft = self.remittance_nft.get();
 
         {
             let nft = IRemittanceNFT::new(remittance_nft);
 
-            let _ = nft.mint(
+            let nft_id = nft.mint(
                 &mut *self,
                 user,
                 monthly_amount,
@@ -149,33 +343,72 @@ impl OracleVerifier {
                 // history_months.to::<u32>(),
                 total_sent,
             )?;
+            let now = U64::from(self.vm().block_timestamp());
+            self.last_remittance_at.insert(nft_id, now);
+            self.nft_scoring_version.insert(nft_id, self.scoring_model_version.get());
         }
 
         {
+            let version = self.scoring_model_version.get();
             let mut request = self.verification_requests.setter(user);
             request.status.set(U8::from(1)); // Verified
+            request.scoring_model_version.set(version);
+        }
+
+        {
+            let request = self.verification_requests.getter(user);
+            let fee = request.fee_paid.get();
+            let operator_fee = request.operator_fee_paid.get();
+            self.escrowed_fees.set(self.escrowed_fees.get() - fee - operator_fee);
+            self.fee_balance.set(self.fee_balance.get() + fee);
+            let earned = self.operator_fees.get(operator);
+            self.operator_fees.insert(operator, earned + operator_fee);
+            self.total_operator_fees.set(self.total_operator_fees.get() + operator_fee);
         }
 
-        self.vm().emit_log(
-            &VerificationComplete {
+        log(
+            self.vm(),
+            VerificationComplete {
                 user,
+                provider_hash,
                 reliability_score: U256::from(reliability_score),
-            }
-            .encode_data(),
-            2,
+            },
         );
 
         Ok(())
     }
 
+    // Closes a pending request that couldn't be verified and refunds
+    // everything the user paid for it.
+    pub fn fail_verification(&mut self, user: Address) -> Result<(), Vec<u8>> {
+        if !self.can_report(self.vm().msg_sender()) {
+            return Err(b"Only operator".to_vec());
+        }
+        let request = self.verification_requests.getter(user);
+        if request.user.get() == Address::ZERO || request.status.get() != U8::from(0) {
+            return Err(b"Already processed".to_vec());
+        }
+        let refund = request.fee_paid.get() + request.operator_fee_paid.get();
+
+        let mut request = self.verification_requests.setter(user);
+        request.status.set(U8::from(2)); // Failed
+        request.fee_paid.set(U256::ZERO);
+        request.operator_fee_paid.set(U256::ZERO);
+        self.escrowed_fees.set(self.escrowed_fees.get() - refund);
+        if refund > U256::ZERO {
+            self._safe_transfer(user, refund)?;
+        }
+        log(self.vm(), VerificationFailed { user, refund });
+        Ok(())
+    }
+
     pub fn start_monitoring_loan(&mut self, loan_id: U256) -> Result<(), Vec<u8>> {
         if self.vm().msg_sender() != self.loan_manager.get() {
             return Err(b"Only loan manager".to_vec());
         }
 
         self.monitored_loans.insert(loan_id, true);
-        self.vm()
-            .emit_log(&MonitoringStarted { loan_id }.encode_data(), 2);
+        log(self.vm(), MonitoringStarted { loan_id });
         Ok(())
     }
 
@@ -186,58 +419,653 @@ impl OracleVerifier {
         amount: U256,
         loan_id: U256,
     ) -> Result<(), Vec<u8>> {
+        if !self.can_report(self.vm().msg_sender()) {
+            return Err(b"Only operator".to_vec());
+        }
         if !self.monitored_loans.get(loan_id) {
             return Err(b"Loan not monitored".to_vec());
         }
+        // An oversized report is turned away without touching the loan or
+        // the NFT, but the call still succeeds so the rejection and its
+        // event stick.
+        let cap = self.max_remittance_per_report.get();
+        if cap > U256::ZERO && amount > cap {
+            let rejected = self.rejected_remittances.get(loan_id);
+            self.rejected_remittances.insert(loan_id, rejected + U256::from(1));
+            log(self.vm(), RemittanceRejected { loan_id, nft_id, amount, cap });
+            return Ok(());
+        }
+        {
+            let loan_mgr = ILoanManager::new(self.loan_manager.get());
+            if loan_mgr.get_loan_collateral(&*self, loan_id)? != nft_id {
+                return Err(b"NFT does not match loan".to_vec());
+            }
+        }
 
         {
+            // fold the remittance into the NFT's history rather than replace it
             let nft = IRemittanceNFT::new(self.remittance_nft.get());
-            nft.update_remittance(&mut *self, nft_id, amount, amount, U256::from(90u64))?;
+            let (_, monthly_amount, reliability_score, total_sent, _) =
+                nft.get_remittance(&*self, nft_id)?;
+            let months = if monthly_amount == U256::ZERO {
+                U256::ZERO
+            } else {
+                total_sent / monthly_amount
+            };
+            let new_total = total_sent + amount;
+            let new_monthly = new_total / (months + U256::from(1u64));
+            // the score is the share of months paid, and this is one more paid
+            let new_score = (reliability_score * months + U256::from(100u64))
+                / (months + U256::from(1u64));
+            nft.update_remittance(&mut *self, nft_id, new_monthly, new_total, new_score)?;
+            let now = U64::from(self.vm().block_timestamp());
+            self.last_remittance_at.insert(nft_id, now);
         }
 
-        {
+        // the remittance is still recorded when the borrower opted out, or
+        // when it's too small to be worth a repayment; all of it is left over then
+        let mut leftover = amount;
+        if amount >= self.min_remittance_for_repayment.get() {
             let loan_mgr = ILoanManager::new(self.loan_manager.get());
-            loan_mgr.process_auto_repayment(&mut *self, loan_id, amount)?;
+            let repaid = loan_mgr
+                .process_auto_repayment(&mut *self, loan_id, amount)
+                .map_err(Vec::<u8>::from);
+            match repaid {
+                Err(data) if data == b"Auto-repay not enabled" => {}
+                other => leftover = other?,
+            }
         }
+        let outstanding_balance =
+            ILoanManager::new(self.loan_manager.get()).get_outstanding_balance(&*self, loan_id)?;
 
-        self.vm().emit_log(
-            &RemittanceReported {
+        log(
+            self.vm(),
+            RemittanceReported {
                 loan_id,
                 nft_id,
                 amount,
-            }
-            .encode_data(),
-            3,
+                outstanding_balance,
+                leftover,
+            },
         );
         Ok(())
     }
 
     pub fn report_missed_payment(&mut self, loan_id: U256, nft_id: U256) -> Result<(), Vec<u8>> {
-
+        if !self.can_report(self.vm().msg_sender()) {
+            return Err(b"Only operator".to_vec());
+        }
+        let status;
         {
-            // let nft = IRemittanceNFT::new(self.remittance_nft.get());
-            // nft.unstake_nft(&mut *self, nft_id)?;
+            // remittances reported this period count first; it's only a miss
+            // if some of the installment is still unpaid once it's overdue
+            let loan_mgr = ILoanManager::new(self.loan_manager.get());
+            if loan_mgr.get_current_shortfall(&*self, loan_id)? == U256::ZERO {
+                return Err(b"Installment already paid".to_vec());
+            }
+            if !loan_mgr.is_payment_overdue(&*self, loan_id)? {
+                return Err(b"Payment not overdue".to_vec());
+            }
+            // the reporting operator gets the keeper reward if this defaults
+            let keeper = self.vm().msg_sender();
+            loan_mgr.mark_payment_missed(&mut *self, loan_id, keeper)?;
+            status = loan_mgr.get_loan_status(&*self, loan_id)?;
         }
 
+        // A default is flagged, but the NFT stays staked: the borrower can
+        // still cure during the liquidation delay, after which the loan
+        // manager's liquidate takes over the collateral.
+        if status == 3 {
+            log(self.vm(), LoanDefaultReported { loan_id, nft_id });
+        }
+
+        log(self.vm(), PaymentMissedReported { loan_id, nft_id });
+        Ok(())
+    }
+
+    pub fn get_last_remittance_at(&self, nft_id: U256) -> U64 {
+        self.last_remittance_at.get(nft_id)
+    }
+
+    pub fn can_report(&self, who: Address) -> bool {
+        who == self.admin.get() || self.operators.getter(who).active.get()
+    }
+
+    // Only while nothing is held in the current token: bonds, escrowed
+    // request fees and earned fees would otherwise be paid out in the new one.
+    pub fn set_usdc(&mut self, usdc: Address) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if self.total_bonds.get() > U256::ZERO
+            || self.escrowed_fees.get() > U256::ZERO
+            || self.fee_balance.get() > U256::ZERO
+            || self.total_operator_fees.get() > U256::ZERO
         {
-            let loan_mgr = ILoanManager::new(self.loan_manager.get());
-            loan_mgr.mark_payment_missed(&mut *self, loan_id)?;
+            return Err(b"Token balances outstanding".to_vec());
         }
+        self.usdc.set(usdc);
+        Ok(())
+    }
+
+    // bond an operator must have posted to be added
+    pub fn set_operator_bond(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.operator_bond.set(amount);
+        Ok(())
+    }
 
-        self.vm()
-            .emit_log(&PaymentMissedReported { loan_id, nft_id }.encode_data(), 2);
+    // how long a removed operator's bond stays slashable before it can be withdrawn
+    pub fn set_bond_cooldown(&mut self, seconds: u64) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.bond_cooldown.set(U64::from(seconds));
         Ok(())
     }
 
+    // (operator_bond, bond_cooldown)
+    pub fn get_bond_parameters(&self) -> (U256, U64) {
+        (self.operator_bond.get(), self.bond_cooldown.get())
+    }
+
+    // Pulls the operator up to the required bond, so they need to have
+    // approved it beforehand. A bond left over from an earlier stint counts.
+    pub fn add_operator(&mut self, operator: Address) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if self.operators.getter(operator).active.get() {
+            return Err(b"Already operator".to_vec());
+        }
+        let bond = self.operators.getter(operator).bond.get();
+        let top_up = self.operator_bond.get().saturating_sub(bond);
+        if top_up > U256::ZERO {
+            let contract = self.vm().contract_address();
+            self._safe_transfer_from(operator, contract, top_up)?;
+        }
+
+        let mut entry = self.operators.setter(operator);
+        entry.active.set(true);
+        entry.bond.set(bond + top_up);
+        entry.removed_at.set(U64::ZERO);
+        self.total_bonds.set(self.total_bonds.get() + top_up);
+
+        let event = OperatorAdded { operator, bond: bond + top_up };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    // the bond stays behind, slashable, until the cooldown runs out
+    pub fn remove_operator(&mut self, operator: Address) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if !self.operators.getter(operator).active.get() {
+            return Err(b"Not operator".to_vec());
+        }
+        let now = U64::from(self.vm().block_timestamp());
+        let mut entry = self.operators.setter(operator);
+        entry.active.set(false);
+        entry.removed_at.set(now);
+
+        log(self.vm(), OperatorRemoved { operator });
+        Ok(())
+    }
+
+    pub fn withdraw_bond(&mut self) -> Result<(), Vec<u8>> {
+        let operator = self.vm().msg_sender();
+        let entry = self.operators.getter(operator);
+        if entry.active.get() {
+            return Err(b"Operator still active".to_vec());
+        }
+        let bond = entry.bond.get();
+        if bond == U256::ZERO {
+            return Err(b"No bond".to_vec());
+        }
+        let unlocks_at = entry.removed_at.get().saturating_add(self.bond_cooldown.get());
+        if U64::from(self.vm().block_timestamp()) < unlocks_at {
+            return Err(b"Bond cooldown active".to_vec());
+        }
+
+        self.operators.setter(operator).bond.set(U256::ZERO);
+        self.total_bonds.set(self.total_bonds.get() - bond);
+        self._safe_transfer(operator, bond)?;
+        Ok(())
+    }
+
+    // confiscated bond goes to the admin
+    pub fn slash_operator(
+        &mut self,
+        operator: Address,
+        amount: U256,
+        reason_code: U8,
+    ) -> Result<(), Vec<u8>> {
+        let admin = self.admin.get();
+        if admin != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        let bond = self.operators.getter(operator).bond.get();
+        if amount == U256::ZERO || amount > bond {
+            return Err(b"Invalid slash amount".to_vec());
+        }
+
+        self.operators.setter(operator).bond.set(bond - amount);
+        self.total_bonds.set(self.total_bonds.get() - amount);
+        self._safe_transfer(admin, amount)?;
+
+        let event = OperatorSlashed {
+            operator,
+            amount,
+            reason_code: reason_code.to::<u8>(),
+        };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    // Weight is in bps of the raw score: BPS_DENOMINATOR trusts the provider
+    // fully, less discounts the scores it backs. Providers never configured
+    // are trusted fully and free.
+    pub fn set_provider_config(
+        &mut self,
+        provider: String,
+        trust_weight_bps: u32,
+        verification_fee: U256,
+    ) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        if trust_weight_bps > BPS_DENOMINATOR {
+            return Err(b"Invalid trust weight".to_vec());
+        }
+        let provider_hash = keccak(provider.as_bytes());
+        let mut config = self.provider_configs.setter(provider_hash);
+        config.configured.set(true);
+        config.trust_weight_bps.set(U32::from(trust_weight_bps));
+        config.verification_fee.set(verification_fee);
+        // the trust weight feeds every score this provider backs from here
+        self._bump_scoring_model_version();
+
+        let event = ProviderConfigured {
+            provider_hash,
+            trust_weight_bps,
+            verification_fee,
+        };
+        log(self.vm(), event);
+        Ok(())
+    }
+
+    // (trust_weight_bps, verification_fee)
+    pub fn get_provider_config(&self, provider: String) -> (U32, U256) {
+        let config = self.provider_configs.getter(keccak(provider.as_bytes()));
+        if !config.configured.get() {
+            return (U32::from(BPS_DENOMINATOR), U256::ZERO);
+        }
+        (config.trust_weight_bps.get(), config.verification_fee.get())
+    }
+
+    pub fn get_fee_balance(&self) -> U256 {
+        self.fee_balance.get()
+    }
+
+    pub fn withdraw_fees(&mut self, to: Address, amount: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        let balance = self.fee_balance.get();
+        if amount > balance {
+            return Err(b"Insufficient fees".to_vec());
+        }
+        self.fee_balance.set(balance - amount);
+        self._safe_transfer(to, amount)?;
+        Ok(())
+    }
+
+    pub fn set_verification_fee(&mut self, fee: U256) -> Result<(), Vec<u8>> {
+        if self.admin.get() != self.vm().msg_sender() {
+            return Err(b"Only admin".to_vec());
+        }
+        self.verification_fee.set(fee);
+        Ok(())
+    }
+
+    pub fn get_verification_fee(&self) -> U256 {
+        self.verification_fee.get()
+    }
+
+    pub fn get_operator_fees(&self, operator: Address) -> U256 {
+        self.operator_fees.get(operator)
+    }
+
+    // pays out the verification fees the caller has earned
+    pub fn claim_operator_fees(&mut self) -> Result<U256, Vec<u8>> {
+        let operator = self.vm().msg_sender();
+        let amount = self.operator_fees.get(operator);
+        if amount == U256::ZERO {
+            return Err(b"No fees".to_vec());
+        }
+        self.operator_fees.insert(operator, U256::ZERO);
+        self.total_operator_fees.set(self.total_operator_fees.get() - amount);
+        self._safe_transfer(operator, amount)?;
+        Ok(amount)
+    }
+
+    // (active, bond, removed_at)
+    pub fn get_operator(&self, operator: Address) -> (bool, U256, U64) {
+        let entry = self.operators.getter(operator);
+        (entry.active.get(), entry.bond.get(), entry.removed_at.get())
+    }
+
     pub fn get_verification_status(&self, user: Address) -> U8 {
         self.verification_requests.get(user).status.get()
     }
 
-    fn _calculate_reliability_score(paid: U32, total: U32) -> u32 {
+    fn _bump_scoring_model_version(&mut self) {
+        let version = self.scoring_model_version.get();
+        self.scoring_model_version.set(version + U32::from(1));
+    }
+
+    // With no payment history there's nothing to score, so users get the
+    // neutral baseline rather than a perfect score and the cheapest rate.
+    fn _calculate_reliability_score(paid: U32, total: U32, no_history_score: U32) -> u32 {
         if total == U32::from(0u64) {
-            100
+            no_history_score.to::<u32>()
         } else {
             ((paid * U32::from(100u64)) / total).to::<u32>()
         }
     }
 }
+
+impl OracleVerifier {
+    fn _apply_trust_weight(&self, provider_hash: B256, score: u32) -> u32 {
+        let config = self.provider_configs.getter(provider_hash);
+        if !config.configured.get() {
+            return score;
+        }
+        let weight = config.trust_weight_bps.get().to::<u32>();
+        (u64::from(score) * u64::from(weight) / u64::from(BPS_DENOMINATOR)) as u32
+    }
+
+    fn _safe_transfer(&mut self, to: Address, value: U256) -> Result<(), Vec<u8>> {
+        self._call_token(&transferCall { to, value }.abi_encode())
+    }
+
+    fn _safe_transfer_from(
+        &mut self,
+        from: Address,
+        to: Address,
+        value: U256,
+    ) -> Result<(), Vec<u8>> {
+        self._call_token(&transferFromCall { from, to, value }.abi_encode())
+    }
+
+    // tokens that return nothing are taken at their word, the rest must return true
+    fn _call_token(&mut self, calldata: &[u8]) -> Result<(), Vec<u8>> {
+        let token = self.usdc.get();
+        let returned = self.vm().call(&Call::new(), token, calldata)?;
+        if !returned.is_empty() && !bool::abi_decode(&returned, false).unwrap_or(false) {
+            return Err(b"Token transfer failed".to_vec());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_hostio::*;
+    use stylus_sdk::testing::*;
+
+    // the loan manager's functions as its export-abi prints them
+    mod loan_manager_abi {
+        alloy_sol_types::sol! {
+            function markPaymentMissed(uint256 loan_id, address keeper) external;
+            function getCurrentShortfall(uint256 loan_id) external view returns (uint256);
+            function isPaymentOverdue(uint256 loan_id) external view returns (bool);
+            function getLoanStatus(uint256 loan_id) external view returns (uint8);
+            function getLoanCollateral(uint256 loan_id) external view returns (uint256);
+            function processAutoRepayment(uint256 loan_id, uint256 amount) external returns (uint256);
+            function getOutstandingBalance(uint256 loan_id) external view returns (uint256);
+        }
+    }
+
+    mod nft_abi {
+        alloy_sol_types::sol! {
+            function mint(
+                address user,
+                uint256 monthly_amount,
+                uint256 reliability_score,
+                uint256 total_sent
+            ) external returns (uint256);
+            function getRemittance(uint256 token_id)
+                external
+                view
+                returns (address, uint256, uint256, uint256, bool);
+            function update_remittance(
+                uint256 token_id,
+                uint256 new_monthly_amount,
+                uint256 new_total_sent,
+                uint256 new_reliability_score
+            ) external;
+        }
+    }
+
+    const ADMIN: Address = Address::repeat_byte(0xad);
+    const LOAN_MANAGER: Address = Address::repeat_byte(0x1a);
+    const NFT: Address = Address::repeat_byte(0x2b);
+
+    fn setup() -> (TestVM, OracleVerifier) {
+        let vm = TestVM::default();
+        vm.set_sender(ADMIN);
+        let mut oracle = OracleVerifier::from(&vm);
+        oracle.initialize().unwrap();
+        oracle.set_addresses(NFT, LOAN_MANAGER).unwrap();
+        (vm, oracle)
+    }
+
+    #[test]
+    fn missed_payment_uses_exported_loan_manager_selectors() {
+        use loan_manager_abi::*;
+        let (_vm, mut oracle) = setup();
+        let loan_id = U256::from(7);
+
+        let shortfall = getCurrentShortfallCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, shortfall, Ok(U256::from(1).abi_encode()));
+        let overdue = isPaymentOverdueCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, overdue, Ok(true.abi_encode()));
+        // only a call with the exported selector reaches this revert
+        let missed = markPaymentMissedCall { loan_id, keeper: ADMIN }.abi_encode();
+        mock_call(LOAN_MANAGER, missed, Err(b"reached".to_vec()));
+
+        let result = oracle.report_missed_payment(loan_id, U256::from(1));
+        assert_eq!(result, Err(b"reached".to_vec()));
+    }
+
+    #[test]
+    fn report_over_the_cap_is_recorded_without_reverting() {
+        let (vm, mut oracle) = setup();
+        let loan_id = U256::from(7);
+        oracle.set_max_remittance_per_report(U256::from(1_000)).unwrap();
+        vm.set_sender(LOAN_MANAGER);
+        oracle.start_monitoring_loan(loan_id).unwrap();
+        vm.set_sender(ADMIN);
+
+        // nothing downstream is mocked, so only the early return can succeed
+        oracle.report_remittance(U256::from(1), U256::from(1_001), loan_id).unwrap();
+        assert_eq!(oracle.get_rejected_remittances(loan_id), U256::from(1));
+        let (topics, _) = vm.get_emitted_logs().pop().unwrap();
+        assert_eq!(topics[0], <RemittanceRejected as alloy_sol_types::SolEvent>::SIGNATURE_HASH);
+    }
+
+    #[test]
+    fn reported_remittance_counts_as_a_paid_month_in_the_score() {
+        let (vm, mut oracle) = setup();
+        let (loan_id, nft_id) = (U256::from(7), U256::from(1));
+        vm.set_sender(LOAN_MANAGER);
+        oracle.start_monitoring_loan(loan_id).unwrap();
+        vm.set_sender(ADMIN);
+
+        let collateral = loan_manager_abi::getLoanCollateralCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, collateral, Ok(nft_id.abi_encode()));
+        // four months at 1,000 scored 80, then a fifth paid month
+        let history = (ADMIN, U256::from(1_000), U256::from(80), U256::from(4_000), true);
+        let remittance = nft_abi::getRemittanceCall { token_id: nft_id }.abi_encode();
+        mock_call(NFT, remittance, Ok(history.abi_encode_params()));
+        let update = nft_abi::update_remittanceCall {
+            token_id: nft_id,
+            new_monthly_amount: U256::from(1_000),
+            new_total_sent: U256::from(5_000),
+            new_reliability_score: U256::from(84),
+        }
+        .abi_encode();
+        mock_call(NFT, update, Err(b"reached".to_vec()));
+
+        let result = oracle.report_remittance(nft_id, U256::from(1_000), loan_id);
+        assert_eq!(result, Err(b"reached".to_vec()));
+    }
+
+    #[test]
+    fn provider_config_bumps_the_scoring_model_version() {
+        let (_vm, mut oracle) = setup();
+        let version = oracle.get_scoring_model_version();
+        oracle.set_provider_config(String::from("wise"), 9_000, U256::ZERO).unwrap();
+        assert_eq!(oracle.get_scoring_model_version(), version + U32::from(1));
+    }
+
+    #[test]
+    fn usdc_is_fixed_while_bonds_are_held() {
+        let (_vm, mut oracle) = setup();
+        let operator = Address::repeat_byte(0x0b);
+        oracle.set_usdc(Address::repeat_byte(0x0c)).unwrap();
+        oracle.set_operator_bond(U256::from(1_000)).unwrap();
+        oracle.add_operator(operator).unwrap();
+        assert_eq!(
+            oracle.set_usdc(Address::repeat_byte(0x0d)),
+            Err(b"Token balances outstanding".to_vec())
+        );
+
+        oracle.slash_operator(operator, U256::from(1_000), U8::from(1)).unwrap();
+        oracle.set_usdc(Address::repeat_byte(0x0d)).unwrap();
+    }
+
+    #[test]
+    fn remittance_reported_decodes_with_both_indexed_fields() {
+        use loan_manager_abi::*;
+        let (vm, mut oracle) = setup();
+        let (loan_id, nft_id, amount) = (U256::from(7), U256::from(1), U256::from(1_000));
+        vm.set_sender(LOAN_MANAGER);
+        oracle.start_monitoring_loan(loan_id).unwrap();
+        vm.set_sender(ADMIN);
+
+        let collateral = getLoanCollateralCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, collateral, Ok(nft_id.abi_encode()));
+        let history = (ADMIN, amount, U256::from(80), U256::from(4_000), true);
+        let remittance = nft_abi::getRemittanceCall { token_id: nft_id }.abi_encode();
+        mock_call(NFT, remittance, Ok(history.abi_encode_params()));
+        let repay = processAutoRepaymentCall { loan_id, amount }.abi_encode();
+        mock_call(LOAN_MANAGER, repay, Ok(U256::from(100).abi_encode()));
+        let balance = getOutstandingBalanceCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, balance, Ok(U256::from(5_000).abi_encode()));
+
+        oracle.report_remittance(nft_id, amount, loan_id).unwrap();
+        let (topics, data) = vm.get_emitted_logs().pop().unwrap();
+        assert_eq!(topics.len(), 3);
+        let event = <RemittanceReported as alloy_sol_types::SolEvent>::decode_raw_log(
+            topics, &data, true,
+        )
+        .unwrap();
+        assert_eq!((event.loan_id, event.nft_id, event.amount), (loan_id, nft_id, amount));
+        assert_eq!((event.outstanding_balance, event.leftover), (U256::from(5_000), U256::from(100)));
+    }
+
+    #[test]
+    fn verification_events_decode() {
+        let (vm, mut oracle) = setup();
+        let (user, other) = (Address::repeat_byte(0xb0), Address::repeat_byte(0xb1));
+        let provider_hash = keccak("wise".as_bytes());
+        oracle.set_provider_config(String::from("wise"), 10_000, U256::ZERO).unwrap();
+        let configured = last_event::<ProviderConfigured>(&vm);
+        assert_eq!((configured.provider_hash, configured.trust_weight_bps), (provider_hash, 10_000));
+        assert_eq!(configured.verification_fee, U256::ZERO);
+
+        vm.set_sender(user);
+        oracle.request_verification(String::from("wise"), String::from("acct")).unwrap();
+        let requested = last_event::<VerificationRequested>(&vm);
+        assert_eq!((requested.user, requested.provider_hash), (user, provider_hash));
+
+        let mint = nft_abi::mintCall {
+            user,
+            monthly_amount: U256::from(1_000),
+            reliability_score: U256::from(100),
+            total_sent: U256::from(12_000),
+        }
+        .abi_encode();
+        mock_call(NFT, mint, Ok(U256::from(1).abi_encode()));
+        vm.set_sender(ADMIN);
+        let (monthly, total) = (U256::from(1_000), U256::from(12_000));
+        oracle.submit_verification(user, monthly, total, U32::from(12), U32::from(12)).unwrap();
+        let complete = last_event::<VerificationComplete>(&vm);
+        assert_eq!((complete.user, complete.provider_hash), (user, provider_hash));
+        assert_eq!(complete.reliability_score, U256::from(100));
+
+        vm.set_sender(other);
+        oracle.request_verification(String::from("wise"), String::from("acct")).unwrap();
+        vm.set_sender(ADMIN);
+        oracle.fail_verification(other).unwrap();
+        let failed = last_event::<VerificationFailed>(&vm);
+        assert_eq!((failed.user, failed.refund), (other, U256::ZERO));
+    }
+
+    #[test]
+    fn report_events_decode() {
+        use loan_manager_abi::*;
+        let (vm, mut oracle) = setup();
+        let (loan_id, nft_id) = (U256::from(7), U256::from(1));
+        vm.set_sender(LOAN_MANAGER);
+        oracle.start_monitoring_loan(loan_id).unwrap();
+        assert_eq!(last_event::<MonitoringStarted>(&vm).loan_id, loan_id);
+
+        vm.set_sender(ADMIN);
+        let cap = U256::from(1_000);
+        oracle.set_max_remittance_per_report(cap).unwrap();
+        assert_eq!(last_event::<MaxRemittancePerReportUpdated>(&vm).cap, cap);
+        oracle.report_remittance(nft_id, cap + U256::from(1), loan_id).unwrap();
+        let rejected = last_event::<RemittanceRejected>(&vm);
+        assert_eq!((rejected.loan_id, rejected.nft_id), (loan_id, nft_id));
+        assert_eq!((rejected.amount, rejected.cap), (cap + U256::from(1), cap));
+
+        // the second miss defaults the loan
+        let shortfall = getCurrentShortfallCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, shortfall, Ok(U256::from(1).abi_encode()));
+        let overdue = isPaymentOverdueCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, overdue, Ok(true.abi_encode()));
+        let status = getLoanStatusCall { loan_id }.abi_encode();
+        mock_call(LOAN_MANAGER, status, Ok(U256::from(3).abi_encode()));
+        oracle.report_missed_payment(loan_id, nft_id).unwrap();
+        let missed = last_event::<PaymentMissedReported>(&vm);
+        assert_eq!((missed.loan_id, missed.nft_id), (loan_id, nft_id));
+        let defaulted = last_event::<LoanDefaultReported>(&vm);
+        assert_eq!((defaulted.loan_id, defaulted.nft_id), (loan_id, nft_id));
+    }
+
+    #[test]
+    fn operator_events_decode() {
+        let (vm, mut oracle) = setup();
+        let operator = Address::repeat_byte(0x0b);
+        let bond = U256::from(1_000);
+        oracle.set_usdc(Address::repeat_byte(0x0c)).unwrap();
+        oracle.set_operator_bond(bond).unwrap();
+        oracle.add_operator(operator).unwrap();
+        let added = last_event::<OperatorAdded>(&vm);
+        assert_eq!((added.operator, added.bond), (operator, bond));
+
+        oracle.remove_operator(operator).unwrap();
+        assert_eq!(last_event::<OperatorRemoved>(&vm).operator, operator);
+        oracle.slash_operator(operator, U256::from(400), U8::from(2)).unwrap();
+        let slashed = last_event::<OperatorSlashed>(&vm);
+        assert_eq!((slashed.operator, slashed.amount), (operator, U256::from(400)));
+        assert_eq!(slashed.reason_code, 2);
+    }
+}
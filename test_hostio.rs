@@ -0,0 +1,113 @@
+// sol_interface! calls still go through the raw call hostios, which TestVM
+// doesn't stand in for. These native definitions answer them from a
+// per-thread table keyed by (contract, calldata); anything not mocked
+// succeeds with no return data, the same as an unmocked TestVM call.
+//
+// Each contract crate includes this one file by path, so not every crate
+// uses every helper.
+#![allow(dead_code)]
+
+use std::{boxed::Box, cell::RefCell, collections::HashMap, rc::Rc, vec::Vec};
+use stylus_sdk::alloy_primitives::Address;
+
+type Answer = Rc<dyn Fn() -> Result<Vec<u8>, Vec<u8>>>;
+type Calls = HashMap<(Address, Vec<u8>), Answer>;
+
+thread_local! {
+    static CALLS: RefCell<Calls> = RefCell::new(HashMap::new());
+    static RETURN_DATA: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn mock_call(to: Address, calldata: Vec<u8>, result: Result<Vec<u8>, Vec<u8>>) {
+    mock_call_with(to, calldata, Box::new(move || result.clone()));
+}
+
+// runs `answer` in the middle of the caller's call, e.g. to call back into
+// the contract the way a hostile callee would
+pub fn mock_call_with(
+    to: Address,
+    calldata: Vec<u8>,
+    answer: Box<dyn Fn() -> Result<Vec<u8>, Vec<u8>>>,
+) {
+    CALLS.with(|calls| calls.borrow_mut().insert((to, calldata), Rc::from(answer)));
+}
+
+unsafe fn answer(contract: *const u8, calldata: *const u8, len: usize, out_len: *mut usize) -> u8 {
+    let to = Address::from_slice(core::slice::from_raw_parts(contract, 20));
+    let data = core::slice::from_raw_parts(calldata, len).to_vec();
+    let answer = CALLS.with(|calls| calls.borrow().get(&(to, data)).cloned());
+    let result = answer.map_or(Ok(Vec::new()), |answer| answer());
+    let (status, returned) = match result {
+        Ok(returned) => (0, returned),
+        Err(returned) => (1, returned),
+    };
+    *out_len = returned.len();
+    RETURN_DATA.with(|data| *data.borrow_mut() = returned);
+    status
+}
+
+#[no_mangle]
+unsafe extern "C" fn call_contract(
+    contract: *const u8,
+    calldata: *const u8,
+    calldata_len: usize,
+    _value: *const u8,
+    _gas: u64,
+    return_data_len: *mut usize,
+) -> u8 {
+    answer(contract, calldata, calldata_len, return_data_len)
+}
+
+#[no_mangle]
+unsafe extern "C" fn static_call_contract(
+    contract: *const u8,
+    calldata: *const u8,
+    calldata_len: usize,
+    _gas: u64,
+    return_data_len: *mut usize,
+) -> u8 {
+    answer(contract, calldata, calldata_len, return_data_len)
+}
+
+#[no_mangle]
+unsafe extern "C" fn delegate_call_contract(
+    contract: *const u8,
+    calldata: *const u8,
+    calldata_len: usize,
+    _gas: u64,
+    return_data_len: *mut usize,
+) -> u8 {
+    answer(contract, calldata, calldata_len, return_data_len)
+}
+
+#[no_mangle]
+unsafe extern "C" fn read_return_data(dest: *mut u8, offset: usize, size: usize) -> usize {
+    RETURN_DATA.with(|data| {
+        let data = data.borrow();
+        let start = offset.min(data.len());
+        let end = offset.saturating_add(size).min(data.len());
+        core::ptr::copy_nonoverlapping(data[start..].as_ptr(), dest, end - start);
+        end - start
+    })
+}
+
+#[no_mangle]
+extern "C" fn return_data_size() -> usize {
+    RETURN_DATA.with(|data| data.borrow().len())
+}
+
+#[no_mangle]
+extern "C" fn storage_flush_cache(_clear: bool) {}
+
+// the last E the contract logged, checked to carry exactly E's topics so an
+// indexed field can't silently become data, then decoded
+pub fn last_event<E: alloy_sol_types::SolEvent>(vm: &stylus_sdk::testing::TestVM) -> E {
+    let (topics, data) = vm
+        .get_emitted_logs()
+        .into_iter()
+        .rev()
+        .find(|(topics, _)| topics.first() == Some(&E::SIGNATURE_HASH))
+        .expect("event not logged");
+    assert_eq!(topics.len(), <E::TopicList as alloy_sol_types::TopicList>::COUNT);
+    E::decode_raw_log(topics, &data, true).unwrap()
+}